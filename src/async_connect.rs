@@ -0,0 +1,183 @@
+//! Connecting as a [`Future`], for async code. Only with the `async` feature.
+
+use super::*;
+use log::trace;
+use std::{
+	future::Future,
+	io, mem, net,
+	pin::Pin,
+	sync::{Arc, Mutex, PoisonError},
+	task::{Context, Poll, Wake, Waker},
+	time,
+};
+
+/// An async runtime's reactor, with which a [`WakerNotifier`] registers fds and instants so as to wake the task driving a connection, rather than poll the connection itself.
+pub trait Reactor {
+	/// Returned by [`add_instant`](Reactor::add_instant), to cancel it.
+	type InstantSlot: Send + Sync + 'static;
+	/// Wake `waker` on each edge-triggered event on this file descriptor, until it's removed.
+	fn add_fd(&self, fd: Fd, waker: &Waker);
+	/// No longer wake on events on this file descriptor.
+	fn remove_fd(&self, fd: Fd);
+	/// Wake `waker` at this (typically future) instant, or as soon as possible if it has already passed.
+	fn add_instant(&self, instant: time::Instant, waker: &Waker) -> Self::InstantSlot;
+	/// No longer wake at this specific previously added instant.
+	fn remove_instant(&self, slot: Self::InstantSlot);
+}
+
+/// A [`Notifier`] that wakes the task driving a connection, via a [`Reactor`], rather than polling it.
+///
+/// The waker it registers with the reactor wakes whichever task last called [`register`](WakerNotifier::register), so fds and instants added while one task was driving the connection go on to wake the next.
+pub struct WakerNotifier<'a, R: Reactor> {
+	reactor: &'a R,
+	task: Arc<TaskWaker>,
+	waker: Waker,
+}
+impl<'a, R: Reactor> WakerNotifier<'a, R> {
+	#[must_use]
+	pub fn new(reactor: &'a R) -> Self {
+		let task = Arc::new(TaskWaker(Mutex::new(None)));
+		let waker = Waker::from(task.clone());
+		Self {
+			reactor,
+			task,
+			waker,
+		}
+	}
+	/// Wake the task of `cx` on events from now on. Call this on each poll of a future driving a connection with this notifier, before polling the connection.
+	pub fn register(&self, cx: &Context<'_>) {
+		let mut task = self.task.0.lock().unwrap_or_else(PoisonError::into_inner);
+		if !task
+			.as_ref()
+			.is_some_and(|waker| waker.will_wake(cx.waker()))
+		{
+			*task = Some(cx.waker().clone());
+		}
+	}
+}
+impl<R: Reactor> Notifier for WakerNotifier<'_, R> {
+	type InstantSlot = R::InstantSlot;
+	fn queue(&self) {
+		self.waker.wake_by_ref();
+	}
+	fn add_fd(&self, fd: Fd) {
+		self.reactor.add_fd(fd, &self.waker);
+	}
+	fn remove_fd(&self, fd: Fd) {
+		self.reactor.remove_fd(fd);
+	}
+	fn add_instant(&self, instant: time::Instant) -> Self::InstantSlot {
+		self.reactor.add_instant(instant, &self.waker)
+	}
+	fn remove_instant(&self, slot: Self::InstantSlot) {
+		self.reactor.remove_instant(slot);
+	}
+}
+impl<R: Reactor> fmt::Debug for WakerNotifier<'_, R> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("WakerNotifier").finish()
+	}
+}
+
+// Forwards wakes to the task that last registered.
+struct TaskWaker(Mutex<Option<Waker>>);
+impl Wake for TaskWaker {
+	fn wake(self: Arc<Self>) {
+		self.wake_by_ref();
+	}
+	fn wake_by_ref(self: &Arc<Self>) {
+		if let Some(waker) = &*self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+			waker.wake_by_ref();
+		}
+	}
+}
+
+/// Connect to `remote` from `local`, resolving to the [`Connected`] once established, or to an error if it isn't within `timeout`.
+///
+/// A [`Connecter`] retries refused connections until it succeeds, so a refusal surfaces as `TimedOut` once `timeout` has passed. Failing to create or bind the socket, as when no local port is free, resolves to an `Other` error. If the future is dropped before resolving, the connection is killed.
+///
+/// The [`Connected`] remains registered with `notifier`'s reactor, and is to go on being driven with `notifier`.
+#[must_use]
+pub fn connect<'a, R: Reactor>(
+	local: net::SocketAddr, remote: net::SocketAddr, timeout: time::Duration,
+	notifier: &'a WakerNotifier<'a, R>,
+) -> Connect<'a, R> {
+	Connect {
+		state: ConnectState::Start(local, remote),
+		timeout,
+		deadline: Timer::new(),
+		notifier,
+	}
+}
+
+/// The future returned by [`connect`].
+pub struct Connect<'a, R: Reactor> {
+	state: ConnectState,
+	timeout: time::Duration,
+	deadline: Timer,
+	notifier: &'a WakerNotifier<'a, R>,
+}
+enum ConnectState {
+	Start(net::SocketAddr, net::SocketAddr),
+	Connecting(Connecter),
+	Done,
+}
+impl<R: Reactor> Future for Connect<'_, R> {
+	type Output = io::Result<Connected>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = &mut *self;
+		let notifier = this.notifier;
+		notifier.register(cx);
+		let poll = match mem::replace(&mut this.state, ConnectState::Done) {
+			ConnectState::Start(local, remote) => {
+				this.deadline
+					.set(time::Instant::now() + this.timeout, notifier);
+				Connecter::new(local, remote, notifier)
+			}
+			ConnectState::Connecting(connecter) => connecter.poll(notifier),
+			ConnectState::Done => panic!("Connect polled after completion"),
+		};
+		let ret = match poll {
+			ConnecterPoll::Connected(connected) => Ok(connected),
+			ConnecterPoll::RemoteClosed(remote_closed) => {
+				remote_closed.kill(notifier);
+				Err(io::ErrorKind::UnexpectedEof.into())
+			}
+			ConnecterPoll::Killed => Err(io::Error::other("failed to create or bind the socket")),
+			ConnecterPoll::Connecter(connecter) => {
+				if this
+					.deadline
+					.instant()
+					.is_none_or(|deadline| time::Instant::now() < deadline)
+				{
+					this.state = ConnectState::Connecting(connecter);
+					return Poll::Pending;
+				}
+				trace!("Connect timed out");
+				connecter.kill(notifier);
+				Err(io::ErrorKind::TimedOut.into())
+			}
+		};
+		this.deadline.cancel(notifier);
+		Poll::Ready(ret)
+	}
+}
+impl<R: Reactor> Drop for Connect<'_, R> {
+	fn drop(&mut self) {
+		if let ConnectState::Connecting(connecter) =
+			mem::replace(&mut self.state, ConnectState::Done)
+		{
+			connecter.kill(self.notifier);
+			self.deadline.cancel(self.notifier);
+		}
+	}
+}
+impl<R: Reactor> fmt::Debug for Connect<'_, R> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Connect")
+			.field("timeout", &self.timeout)
+			.field("deadline", &self.deadline)
+			.finish()
+	}
+}
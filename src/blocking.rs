@@ -0,0 +1,64 @@
+//! Blocking on a single [`Connection`], for test harnesses and simple synchronous clients that don't want a reactor.
+
+use super::*;
+use nix::{errno, libc, poll};
+use std::{convert::TryInto, mem, thread, time};
+
+/// Whether [`Connection::poll_for`] found the connection to have changed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PollOutcome {
+	/// Its state, or how much can be received or sent, changed.
+	Changed,
+	/// Nothing became ready before the timeout, or what did didn't amount to anything.
+	Unchanged,
+}
+
+impl Connection {
+	/// Wait up to `timeout` for the socket to become ready for what the connection is waiting on (its [`interest`](Connection::interest)), with `poll(2)`, then [`poll`](Connection::poll) it once.
+	///
+	/// As `poll(2)` is level-triggered, this doesn't rely on events being delivered through the `executor`: polls it's asked to queue, and instants it's given, are ignored, other than by being caught by the next call. So this should be called in a loop, with a timeout no longer than can be waited before timers such as [`ConnectionConfig::close_timeout`] are acted on. Where there's no socket to wait on, as between a [`Connecter`]'s attempts, this sleeps for `timeout`.
+	///
+	/// # Panics
+	///
+	/// If `poll(2)` fails other than by being interrupted.
+	pub fn poll_for(&mut self, timeout: time::Duration, executor: &impl Notifier) -> PollOutcome {
+		if self.is_terminal() {
+			return PollOutcome::Unchanged;
+		}
+		if let Some(fd) = self.fd() {
+			let interest = self.interest();
+			let mut events = poll::PollFlags::empty();
+			events.set(poll::PollFlags::POLLIN, interest.read);
+			events.set(poll::PollFlags::POLLOUT, interest.write);
+			// rounded up, so as not to spin on sub-millisecond timeouts
+			let timeout: libc::c_int = timeout
+				.as_micros()
+				.div_ceil(1000)
+				.try_into()
+				.unwrap_or(libc::c_int::MAX);
+			match poll::poll(&mut [poll::PollFd::new(fd, events)], timeout) {
+				Ok(_) | Err(nix::Error::Sys(errno::Errno::EINTR)) => (),
+				Err(err) => panic!("poll err {:?}", err),
+			}
+		} else {
+			thread::sleep(timeout);
+		}
+		let before = snapshot(self);
+		self.poll(executor);
+		if snapshot(self) != before {
+			PollOutcome::Changed
+		} else {
+			PollOutcome::Unchanged
+		}
+	}
+}
+
+fn snapshot(
+	connection: &Connection,
+) -> (mem::Discriminant<Connection>, Option<usize>, Option<usize>) {
+	(
+		mem::discriminant(connection),
+		connection.recv_avail(),
+		connection.send_avail(),
+	)
+}
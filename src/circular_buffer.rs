@@ -1,5 +1,7 @@
 use nix;
-use std::{cmp, fmt, os, ptr};
+#[cfg(any(target_os = "android", target_os = "linux"))]
+use std::mem;
+use std::{cmp, convert::TryInto, fmt, os, ptr, time};
 
 #[derive(Clone)]
 pub struct CircularBuffer<T> {
@@ -33,6 +35,19 @@ impl<T> CircularBuffer<T> {
 	pub fn write_available(&self) -> usize {
 		self.capacity() - self.read_available()
 	}
+	/// Drop all readable elements.
+	pub fn discard(&mut self) {
+		while let Some(read) = self.read() {
+			let _ = read();
+		}
+	}
+	pub fn into_vec(mut self) -> Vec<T> {
+		let mut ret = Vec::with_capacity(self.read_available());
+		while let Some(read) = self.read() {
+			ret.push(read());
+		}
+		ret
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn read<'a>(&'a mut self) -> Option<impl FnOnce() -> T + 'a> {
@@ -69,13 +84,70 @@ impl<T> CircularBuffer<T> {
 }
 impl<T> Drop for CircularBuffer<T> {
 	fn drop(&mut self) {
-		while self.read_available() > 0 {
-			let _ = self.read();
-		}
+		self.discard();
 		unsafe { self.buf.set_len(0) };
 	}
 }
 impl CircularBuffer<u8> {
+	/// Reallocate to `cap`, which must be at least `read_available()`.
+	pub fn resize(&mut self, cap: usize) {
+		assert!(cap >= self.read_available());
+		let mut buf = Self::new(cap);
+		let len = self.read_available();
+		// the contents may wrap around the end
+		let first = cmp::min(len, self.capacity() - self.tail);
+		buf.buf[..first].copy_from_slice(&self.buf[self.tail..self.tail + first]);
+		buf.buf[first..len].copy_from_slice(&self.buf[..len - first]);
+		buf.head = len;
+		// moving the contents isn't reading them
+		buf.read = self.read;
+		buf.written = self.written;
+		// such that dropping the old buffer doesn't drop its moved contents
+		self.tail = self.head;
+		*self = buf;
+	}
+	#[cfg(feature = "diagnostics")]
+	pub fn debug(&self) -> crate::RingDebug {
+		crate::RingDebug {
+			capacity: self.capacity(),
+			read_pos: self.tail,
+			write_pos: self.head % self.capacity(),
+			len: self.read_available(),
+			wrapped: self.head > self.capacity(),
+		}
+	}
+	/// Copy as much of `buf` in as fits, returning how many bytes were written.
+	pub fn write_slice(&mut self, buf: &[u8]) -> usize {
+		let n = cmp::min(buf.len(), self.write_available());
+		if n == 0 {
+			return 0;
+		}
+		let a_start = self.head % self.capacity();
+		let a_len = cmp::min(n, self.capacity() - a_start);
+		self.buf[a_start..a_start + a_len].copy_from_slice(&buf[..a_len]);
+		self.buf[..n - a_len].copy_from_slice(&buf[a_len..n]);
+		self.head += n;
+		self.written += n;
+		n
+	}
+	/// Copy out as much as fits in `buf`, returning how many bytes were read.
+	pub fn read_slice(&mut self, buf: &mut [u8]) -> usize {
+		let n = cmp::min(buf.len(), self.read_available());
+		if n == 0 {
+			return 0;
+		}
+		let a_start = self.tail;
+		let a_len = cmp::min(n, self.capacity() - a_start);
+		buf[..a_len].copy_from_slice(&self.buf[a_start..a_start + a_len]);
+		buf[a_len..n].copy_from_slice(&self.buf[..n - a_len]);
+		self.tail += n;
+		if self.tail >= self.capacity() {
+			self.head -= self.capacity();
+			self.tail -= self.capacity();
+		}
+		self.read += n;
+		n
+	}
 	pub fn read_to_fd(&mut self, fd: os::unix::io::RawFd) -> Result<usize, nix::Error> {
 		let mut written = 0;
 		loop {
@@ -116,28 +188,37 @@ impl CircularBuffer<u8> {
 			}
 		}
 	}
-	pub fn write_from_fd(&mut self, fd: os::unix::io::RawFd) -> Result<(usize, bool), nix::Error> {
+	/// If `timestamp` is given, it's updated with the receive time (relative to the UNIX epoch) of the latest data read: `SO_TIMESTAMPING` or `SO_TIMESTAMPNS` on Linux and Android, and `SO_TIMESTAMP` elsewhere.
+	///
+	/// If full while more data is waiting, the buffer is doubled up to `max_capacity`, if given.
+	pub fn write_from_fd(
+		&mut self, fd: os::unix::io::RawFd, mut timestamp: Option<&mut Option<time::Duration>>,
+		max_capacity: Option<usize>,
+	) -> Result<(usize, bool), nix::Error> {
 		let mut read = 0;
 		loop {
 			if self.write_available() > 0 {
 				let a_start = self.head % self.capacity();
 				let a_end = cmp::min(self.capacity(), a_start + self.write_available());
-				let b_start = 0;
 				let b_end = self.write_available() - (a_end - a_start);
 				let (b, a) = self.buf.split_at_mut(b_end);
-				match nix::sys::socket::recvmsg(
-					fd,
-					&[
-						nix::sys::uio::IoVec::from_mut_slice(
-							&mut a[a_start - b_end..a_end - b_end],
-						),
-						nix::sys::uio::IoVec::from_mut_slice(b),
-					][0..if b_start != b_end { 2 } else { 1 }],
-					None,
-					nix::sys::socket::MsgFlags::empty(),
-				)
-				.map(|x| x.bytes)
-				{
+				let a = &mut a[a_start - b_end..a_end - b_end];
+				let iovs = if b.is_empty() { 1 } else { 2 };
+				let recv = if let Some(timestamp) = timestamp.as_mut() {
+					recv_timestamped(fd, a, b, timestamp)
+				} else {
+					nix::sys::socket::recvmsg(
+						fd,
+						&[
+							nix::sys::uio::IoVec::from_mut_slice(a),
+							nix::sys::uio::IoVec::from_mut_slice(b),
+						][..iovs],
+						None,
+						nix::sys::socket::MsgFlags::empty(),
+					)
+					.map(|x| x.bytes)
+				};
+				match recv {
 					Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => return Ok((read, false)),
 					Ok(0) => return Ok((read, true)),
 					Ok(n) => {
@@ -156,6 +237,9 @@ impl CircularBuffer<u8> {
 				)
 				.map(|x| x.bytes)
 				{
+					Ok(1) if self.capacity() < max_capacity.unwrap_or(0) => {
+						self.resize(cmp::min(self.capacity() * 2, max_capacity.unwrap()));
+					}
 					Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) | Ok(1) => {
 						return Ok((read, false))
 					}
@@ -167,12 +251,106 @@ impl CircularBuffer<u8> {
 		}
 	}
 }
+// Receive into `a` then `b`, updating `timestamp` from the `SO_TIMESTAMPING` or `SO_TIMESTAMPNS` control message, if any. Parsed here as nix doesn't know them.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn recv_timestamped(
+	fd: os::unix::io::RawFd, a: &mut [u8], b: &mut [u8], timestamp: &mut Option<time::Duration>,
+) -> Result<usize, nix::Error> {
+	use nix::libc;
+	let mut iov = [
+		libc::iovec {
+			iov_base: a.as_mut_ptr().cast(),
+			iov_len: a.len(),
+		},
+		libc::iovec {
+			iov_base: b.as_mut_ptr().cast(),
+			iov_len: b.len(),
+		},
+	];
+	// u64s for cmsghdr's alignment; ample for the three timespecs of SCM_TIMESTAMPING
+	let mut cmsg = [0_u64; 16];
+	let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+	msg.msg_iov = iov.as_mut_ptr();
+	msg.msg_iovlen = if b.is_empty() { 1 } else { 2 };
+	msg.msg_control = cmsg.as_mut_ptr().cast();
+	// socklen_t on musl
+	#[allow(clippy::useless_conversion)]
+	{
+		msg.msg_controllen = size_of_val(&cmsg).try_into().unwrap();
+	}
+	let res = unsafe { libc::recvmsg(fd, ptr::addr_of_mut!(msg), 0) };
+	let read = nix::errno::Errno::result(res)?;
+	let mut header = unsafe { libc::CMSG_FIRSTHDR(ptr::addr_of!(msg)) };
+	while !header.is_null() {
+		let (level, kind) = unsafe { ((*header).cmsg_level, (*header).cmsg_type) };
+		if level == libc::SOL_SOCKET && kind == libc::SCM_TIMESTAMPING {
+			// software, deprecated, and raw hardware; unset ones are zero
+			let times: [libc::timespec; 3] =
+				unsafe { ptr::read_unaligned(libc::CMSG_DATA(header).cast()) };
+			let time = if times[2].tv_sec != 0 || times[2].tv_nsec != 0 {
+				times[2]
+			} else {
+				times[0]
+			};
+			if let Some(time) =
+				since_epoch(time.tv_sec, time.tv_nsec).filter(|time| !time.is_zero())
+			{
+				*timestamp = Some(time);
+			}
+		}
+		if level == libc::SOL_SOCKET && kind == libc::SCM_TIMESTAMPNS {
+			let time: libc::timespec =
+				unsafe { ptr::read_unaligned(libc::CMSG_DATA(header).cast()) };
+			if let Some(time) = since_epoch(time.tv_sec, time.tv_nsec) {
+				*timestamp = Some(time);
+			}
+		}
+		header = unsafe { libc::CMSG_NXTHDR(ptr::addr_of!(msg), header) };
+	}
+	Ok(read.try_into().unwrap())
+}
+// Receive into `a` then `b`, updating `timestamp` from the `SO_TIMESTAMP` control message, if any.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn recv_timestamped(
+	fd: os::unix::io::RawFd, a: &mut [u8], b: &mut [u8], timestamp: &mut Option<time::Duration>,
+) -> Result<usize, nix::Error> {
+	let iovs = if b.is_empty() { 1 } else { 2 };
+	let mut cmsg = nix::cmsg_space!(nix::sys::time::TimeVal);
+	let msg = nix::sys::socket::recvmsg(
+		fd,
+		&[
+			nix::sys::uio::IoVec::from_mut_slice(a),
+			nix::sys::uio::IoVec::from_mut_slice(b),
+		][..iovs],
+		Some(&mut cmsg),
+		nix::sys::socket::MsgFlags::empty(),
+	)?;
+	for cmsg in msg.cmsgs() {
+		if let nix::sys::socket::ControlMessageOwned::ScmTimestamp(time) = cmsg {
+			if let Some(time) = since_epoch(time.tv_sec(), i64::from(time.tv_usec()) * 1000) {
+				*timestamp = Some(time);
+			}
+		}
+	}
+	Ok(msg.bytes)
+}
+// None if out of range, as a timestamp before the epoch or a malformed one would be.
+fn since_epoch(secs: impl TryInto<u64>, nanos: impl TryInto<u32>) -> Option<time::Duration> {
+	let nanos = nanos
+		.try_into()
+		.ok()
+		.filter(|&nanos| nanos < 1_000_000_000)?;
+	Some(time::Duration::new(secs.try_into().ok()?, nanos))
+}
+
 impl<T> fmt::Debug for CircularBuffer<T>
 where
 	for<'a> &'a T: fmt::Debug,
 {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
 		fmt.debug_struct("CircularBuffer")
+			.field("head", &self.head)
+			.field("tail", &self.tail)
 			.field("written", &self.written)
 			.field("read", &self.read)
 			.field(
@@ -188,6 +366,6 @@ where
 						.collect::<Vec<_>>()
 				),
 			)
-			.finish()
+			.finish_non_exhaustive()
 	}
 }
@@ -0,0 +1,310 @@
+use std::{
+	fmt, io, ops,
+	sync::{Arc, Mutex, PoisonError},
+	time,
+};
+
+/// Options applied to the listening socket by [`Listener::new_ephemeral_with`](crate::Listener::new_ephemeral_with) and [`Listener::bind_with`](crate::Listener::bind_with).
+#[derive(Clone, Debug)]
+pub struct ListenerConfig {
+	/// Whether an IPv6 listener should only accept IPv6 connections (`IPV6_V6ONLY`). Ignored for IPv4 listeners.
+	///
+	/// Setting this to `false` gives a dualstack listener that also accepts IPv4 connections; their remote addresses are reported as native IPv4 rather than IPv4-mapped IPv6.
+	///
+	/// This is always set explicitly, as the OS defaults differ: Linux defaults to `false` (per the `net.ipv6.bindv6only` sysctl), while macOS, the BSDs and Windows default to `true`.
+	pub ipv6_only: bool,
+	/// Whether accepted connections begin with a [PROXY protocol](https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt) (v1 or v2) header, as sent by load balancers such as HAProxy and AWS NLB.
+	///
+	/// If so, the header is stripped from the byte stream and the client address it carries is used as the remote address. Connections remain [`Connectee`](crate::Connectee)s until it has been received, and are killed if it's invalid.
+	pub proxy_protocol: bool,
+	/// On Linux, the CPU to set as the listening socket's `SO_INCOMING_CPU`. Among listeners sharing a port by `SO_REUSEPORT`, connections are then steered to the one whose CPU processed their packets, so that with an accept thread pinned to each CPU they're handled where they arrive. Ignored on other platforms.
+	pub incoming_cpu: Option<u32>,
+	/// Options for the accepted connections.
+	pub connection: ConnectionConfig,
+}
+impl Default for ListenerConfig {
+	fn default() -> Self {
+		Self {
+			ipv6_only: true,
+			proxy_protocol: false,
+			incoming_cpu: None,
+			connection: ConnectionConfig::default(),
+		}
+	}
+}
+
+/// Per-connection options, as given to [`Connection::connect_with`](crate::Connection::connect_with) or [`ListenerConfig::connection`]. See [`ConnectionConfig::builder`].
+///
+/// The builder checks that the options which must be non-zero are; a config made otherwise is checked when it's used, by [`Connecter::new_with`](crate::Connecter::new_with) and the [`Listener`](crate::Listener) constructors, which panic if it's invalid.
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+	/// What to do when the receive buffer is full.
+	pub recv_full: RecvFull,
+	/// A label to identify the connection by in log output alongside its remote address, and returned by [`Connection::label`](crate::Connection::label).
+	pub label: Option<Arc<str>>,
+	/// For outbound connections, bind to the first free port in this range (on the local address's IP) rather than the local address's port. If none are free the connection is killed.
+	///
+	/// As ports in use need to be detected at bind, `SO_REUSEADDR` and `SO_REUSEPORT` aren't set on these connections, so ports in `TIME_WAIT` are skipped.
+	pub local_port_range: Option<ops::Range<u16>>,
+	/// How long a graceful close may take, from [`close`](crate::Connection::close) until the send buffer is flushed and the remote has closed too, before the connection is reset. For connections closed while still connecting, it runs from when they connect.
+	pub close_timeout: Option<time::Duration>,
+	/// The capacity of the send buffer, in bytes. Must be non-zero. Defaults to 64 KiB.
+	pub send_buf: usize,
+	/// The initial capacity of the receive buffer, in bytes, which may grow per [`recv_full`](ConnectionConfig::recv_full). Must be non-zero. Defaults to 64 KiB.
+	pub recv_buf: usize,
+	/// The capacity both buffers are reduced to by [`Connected::shrink_buffers`](crate::Connected::shrink_buffers), for connections that are mostly idle. Must be non-zero. Defaults to 4 KiB.
+	pub idle_buf: usize,
+	/// Whether [`Connected::send_slice`](crate::Connected::send_slice) of more than the send buffer's capacity writes as much as it can straight to the socket, once the send buffer has been flushed, rather than copying it all through the send buffer. Defaults to `false`.
+	pub send_direct: bool,
+	/// Whether to disable Nagle's algorithm (`TCP_NODELAY`). Defaults to `true`.
+	pub no_delay: bool,
+	/// On Linux and Android, re-set `TCP_QUICKACK` after each read, as the kernel may revert to delayed ACKs at any time, so as to ACK promptly. Ignored on other platforms. Defaults to `false`.
+	pub quick_ack: bool,
+	/// On Linux, clamp the advertised receive window to this many bytes (`TCP_WINDOW_CLAMP`), bounding how much the remote can have in flight and so the kernel memory the connection uses. Ignored on other platforms.
+	///
+	/// The window scale is fixed during the handshake. For outbound connections the clamp is set before connecting, so the scale is chosen to suit it; accepted connections are clamped only once established, at whatever scale was negotiated, which with a large scale makes the clamp coarser.
+	pub window_clamp: Option<u32>,
+	/// On Linux, the path MTU discovery mode (`IP_MTU_DISCOVER`, or `IPV6_MTU_DISCOVER` for IPv6 sockets), which determines whether the Don't Fragment bit is set. Ignored on other platforms. Defaults to the kernel's, per the `net.ipv4.ip_no_pmtu_disc` sysctl.
+	pub pmtu_discovery: Option<PmtuDiscovery>,
+	/// Enable TCP keepalive (`SO_KEEPALIVE`), probing once the connection has been idle for this long (to the second).
+	pub keepalive: Option<time::Duration>,
+	/// The most iterations a single poll may loop for while connecting, such as retrying a connect that failed transiently, before it's deemed a livelock. Must be non-zero. Defaults to [`POLL_LIMIT`](crate::POLL_LIMIT).
+	pub poll_limit: usize,
+	/// What to do on hitting [`poll_limit`](ConnectionConfig::poll_limit). Either way it's counted by [`poll_livelocks`](crate::poll_livelocks).
+	pub poll_limit_action: PollLimitAction,
+	/// Called by [`Connecter`](crate::Connecter) each time it schedules a reconnect. See [`RetryHook`].
+	pub on_retry: Option<RetryHook>,
+}
+impl ConnectionConfig {
+	#[must_use]
+	pub fn builder() -> ConnectionConfigBuilder {
+		ConnectionConfigBuilder(Self::default())
+	}
+	pub(crate) fn validate(&self) {
+		assert_ne!(self.send_buf, 0, "send_buf must be non-zero");
+		assert_ne!(self.recv_buf, 0, "recv_buf must be non-zero");
+		assert_ne!(self.idle_buf, 0, "idle_buf must be non-zero");
+		assert_ne!(self.poll_limit, 0, "poll_limit must be non-zero");
+	}
+}
+impl Default for ConnectionConfig {
+	fn default() -> Self {
+		Self {
+			recv_full: RecvFull::default(),
+			label: None,
+			local_port_range: None,
+			close_timeout: None,
+			send_buf: crate::BUF,
+			recv_buf: crate::BUF,
+			idle_buf: crate::IDLE_BUF,
+			send_direct: false,
+			no_delay: true,
+			quick_ack: false,
+			window_clamp: None,
+			pmtu_discovery: None,
+			keepalive: None,
+			poll_limit: crate::POLL_LIMIT,
+			poll_limit_action: PollLimitAction::default(),
+			on_retry: None,
+		}
+	}
+}
+
+/// Builds a [`ConnectionConfig`], starting from the defaults. For example `ConnectionConfig::builder().send_buf(128 * 1024).no_delay(false).keepalive(Duration::from_secs(30)).build()`.
+#[derive(Clone, Debug)]
+pub struct ConnectionConfigBuilder(ConnectionConfig);
+impl ConnectionConfigBuilder {
+	/// See [`ConnectionConfig::recv_full`].
+	#[must_use]
+	pub fn recv_full(mut self, recv_full: RecvFull) -> Self {
+		self.0.recv_full = recv_full;
+		self
+	}
+	/// See [`ConnectionConfig::label`].
+	#[must_use]
+	pub fn label(mut self, label: impl Into<Arc<str>>) -> Self {
+		self.0.label = Some(label.into());
+		self
+	}
+	/// See [`ConnectionConfig::local_port_range`].
+	#[must_use]
+	pub fn local_port_range(mut self, local_port_range: ops::Range<u16>) -> Self {
+		self.0.local_port_range = Some(local_port_range);
+		self
+	}
+	/// See [`ConnectionConfig::close_timeout`].
+	#[must_use]
+	pub fn close_timeout(mut self, close_timeout: time::Duration) -> Self {
+		self.0.close_timeout = Some(close_timeout);
+		self
+	}
+	/// See [`ConnectionConfig::send_buf`].
+	///
+	/// # Panics
+	///
+	/// If `send_buf` is zero.
+	#[must_use]
+	pub fn send_buf(mut self, send_buf: usize) -> Self {
+		assert_ne!(send_buf, 0);
+		self.0.send_buf = send_buf;
+		self
+	}
+	/// See [`ConnectionConfig::recv_buf`].
+	///
+	/// # Panics
+	///
+	/// If `recv_buf` is zero.
+	#[must_use]
+	pub fn recv_buf(mut self, recv_buf: usize) -> Self {
+		assert_ne!(recv_buf, 0);
+		self.0.recv_buf = recv_buf;
+		self
+	}
+	/// See [`ConnectionConfig::idle_buf`].
+	///
+	/// # Panics
+	///
+	/// If `idle_buf` is zero.
+	#[must_use]
+	pub fn idle_buf(mut self, idle_buf: usize) -> Self {
+		assert_ne!(idle_buf, 0);
+		self.0.idle_buf = idle_buf;
+		self
+	}
+	/// See [`ConnectionConfig::send_direct`].
+	#[must_use]
+	pub fn send_direct(mut self, send_direct: bool) -> Self {
+		self.0.send_direct = send_direct;
+		self
+	}
+	/// See [`ConnectionConfig::no_delay`].
+	#[must_use]
+	pub fn no_delay(mut self, no_delay: bool) -> Self {
+		self.0.no_delay = no_delay;
+		self
+	}
+	/// See [`ConnectionConfig::quick_ack`].
+	#[must_use]
+	pub fn quick_ack(mut self, quick_ack: bool) -> Self {
+		self.0.quick_ack = quick_ack;
+		self
+	}
+	/// See [`ConnectionConfig::window_clamp`].
+	#[must_use]
+	pub fn window_clamp(mut self, window_clamp: u32) -> Self {
+		self.0.window_clamp = Some(window_clamp);
+		self
+	}
+	/// See [`ConnectionConfig::pmtu_discovery`].
+	#[must_use]
+	pub fn pmtu_discovery(mut self, pmtu_discovery: PmtuDiscovery) -> Self {
+		self.0.pmtu_discovery = Some(pmtu_discovery);
+		self
+	}
+	/// See [`ConnectionConfig::keepalive`].
+	#[must_use]
+	pub fn keepalive(mut self, keepalive: time::Duration) -> Self {
+		self.0.keepalive = Some(keepalive);
+		self
+	}
+	/// See [`ConnectionConfig::poll_limit`].
+	///
+	/// # Panics
+	///
+	/// If `poll_limit` is zero.
+	#[must_use]
+	pub fn poll_limit(mut self, poll_limit: usize) -> Self {
+		assert_ne!(poll_limit, 0);
+		self.0.poll_limit = poll_limit;
+		self
+	}
+	/// See [`ConnectionConfig::poll_limit_action`].
+	#[must_use]
+	pub fn poll_limit_action(mut self, poll_limit_action: PollLimitAction) -> Self {
+		self.0.poll_limit_action = poll_limit_action;
+		self
+	}
+	/// See [`ConnectionConfig::on_retry`].
+	#[must_use]
+	pub fn on_retry(mut self, on_retry: impl FnMut(u32, &io::Error) + Send + 'static) -> Self {
+		self.0.on_retry = Some(RetryHook::new(on_retry));
+		self
+	}
+	#[must_use]
+	pub fn build(self) -> ConnectionConfig {
+		self.0
+	}
+}
+
+/// What to do when the receive buffer is full and more data is waiting in the kernel.
+///
+/// There are no busy wakeups: reading resumes when a `recv` frees space (which queues a poll), rather than on edge-triggered notifications.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum RecvFull {
+	/// Stop reading, leaving data in the kernel such that TCP flow control applies backpressure to the remote.
+	#[default]
+	Backpressure,
+	/// Double the receive buffer, up to `max` bytes, after which apply backpressure.
+	Grow { max: usize },
+	/// Evict slow consumers: once more than `threshold` bytes are waiting in the kernel, reset the connection, leaving it [`Killed`](crate::Connection::Killed). Until then, apply backpressure.
+	///
+	/// The kernel's receive buffer (`SO_RCVBUF`) must be able to hold more than `threshold`, else backpressure stalls the remote before it's reached.
+	Reset { threshold: usize },
+}
+impl RecvFull {
+	pub(crate) fn max_capacity(self) -> Option<usize> {
+		match self {
+			Self::Backpressure | Self::Reset { .. } => None,
+			Self::Grow { max } => Some(max),
+		}
+	}
+	pub(crate) fn reset_threshold(self) -> Option<usize> {
+		match self {
+			Self::Backpressure | Self::Grow { .. } => None,
+			Self::Reset { threshold } => Some(threshold),
+		}
+	}
+}
+
+/// Per [`ConnectionConfig::pmtu_discovery`], named for Linux's `IP_PMTUDISC_*`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PmtuDiscovery {
+	/// Don't set Don't Fragment, so packets larger than the path MTU are fragmented along the way.
+	Dont,
+	/// Set Don't Fragment and discover the path MTU unless the route says otherwise.
+	Want,
+	/// Always set Don't Fragment and discover the path MTU.
+	Do,
+	/// Set Don't Fragment, but ignore the discovered path MTU.
+	Probe,
+}
+
+/// What to do when a poll hits [`ConnectionConfig::poll_limit`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum PollLimitAction {
+	/// Panic, so the livelock can be debugged.
+	#[default]
+	Panic,
+	/// Log a warning and kill the connection.
+	Kill,
+}
+
+/// A hook for observing [`Connecter`](crate::Connecter)'s reconnects, such as to surface "connecting, attempt N", or to escalate after so many. It's called with [`Connecter::attempts`](crate::Connecter::attempts) and the error that prompted the reconnect: the connect failing, or the local address being unavailable to bind.
+///
+/// It's shared by the connections made with clones of the same [`ConnectionConfig`].
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct RetryHook(Arc<Mutex<dyn FnMut(u32, &io::Error) + Send>>);
+impl RetryHook {
+	pub fn new(f: impl FnMut(u32, &io::Error) + Send + 'static) -> Self {
+		Self(Arc::new(Mutex::new(f)))
+	}
+	pub(crate) fn call(&self, attempts: u32, err: &io::Error) {
+		(self.0.lock().unwrap_or_else(PoisonError::into_inner))(attempts, err);
+	}
+}
+impl fmt::Debug for RetryHook {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("RetryHook").finish_non_exhaustive()
+	}
+}
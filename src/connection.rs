@@ -1,5 +1,5 @@
 use super::*;
-use std::{mem, net};
+use std::{fmt, io, mem, net, net::ToSocketAddrs, ops, time};
 
 /// Essentially a dynamically-typed connection, wrapping all of the individual states in an enum and providing methods that are available or not dynamically (by returning an `Option<impl FnOnce(..)>`).
 #[derive(Debug)]
@@ -12,17 +12,138 @@ pub enum Connection {
 	RemoteClosed(RemoteClosed),
 	LocalClosed(LocalClosed),
 	Closing(Closing),
+	Reset(Reset),
+	Socks5(Socks5),
+	Handshaking(Handshaking),
 	Closed,
 	Killed,
 }
 impl Connection {
+	/// Connect to `remote` from `local`, as with [`Connecter::new`].
 	#[must_use]
 	pub fn connect(
 		local: net::SocketAddr, remote: net::SocketAddr, executor: &impl Notifier,
 	) -> Self {
 		Connecter::new(local, remote, executor).into()
 	}
+	#[must_use]
+	pub fn connect_with(
+		local: net::SocketAddr, remote: net::SocketAddr, config: &ConnectionConfig,
+		executor: &impl Notifier,
+	) -> Self {
+		Connecter::new_with(local, remote, config, executor).into()
+	}
+	/// Connect to `target` through the SOCKS5 proxy at `proxy`, with `config`. This is [`connecting`](Connection::connecting) until the proxy has established the connection.
+	///
+	/// # Panics
+	///
+	/// As [`Socks5::new`], if the domain, username or password is longer than 255 bytes.
+	#[must_use]
+	pub fn connect_socks5(
+		local: net::SocketAddr, proxy: net::SocketAddr, target: Socks5Target, auth: Socks5Auth,
+		config: &ConnectionConfig, executor: &impl Notifier,
+	) -> Self {
+		Socks5::new(local, proxy, target, auth, config, executor).into()
+	}
+	/// Take over the connected `stream`, as with [`Connected::from_std`].
+	///
+	/// # Errors
+	///
+	/// As [`Connected::from_std`].
+	pub fn from_std(
+		stream: net::TcpStream, origin: Origin, executor: &impl Notifier,
+	) -> io::Result<Self> {
+		Connected::from_std(stream, origin, executor).map(Into::into)
+	}
+	/// Take over a connection handed over by another process, as with [`Connected::adopt_fd`].
+	///
+	/// # Errors
+	///
+	/// As [`Connected::adopt_fd`].
+	pub fn adopt_fd(
+		fd: Fd, remote: net::SocketAddr, origin: Origin, assumed_state: AdoptState,
+		executor: &impl Notifier,
+	) -> io::Result<Self> {
+		Connected::adopt_fd(fd, remote, origin, assumed_state, executor).map(Into::into)
+	}
+	/// A pair of connections to each other over loopback, the first dialed and the second accepted, for testing code built on this crate without going through a [`Listener`] and [`Connecter`]. Only with the `testing` feature.
+	///
+	/// # Panics
+	///
+	/// If the loopback connection can't be made.
+	#[cfg(feature = "testing")]
+	pub fn pair(executor: &impl Notifier) -> (Self, Self) {
+		let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+		let dialed = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (accepted, _) = listener.accept().unwrap();
+		(
+			Self::from_std(dialed, Origin::Dialer, executor).unwrap(),
+			Self::from_std(accepted, Origin::Listener, executor).unwrap(),
+		)
+	}
+	/// Connect to `host`, using only its addresses of `family`, from an ephemeral port.
+	///
+	/// `host` is resolved with the system resolver, which blocks, unless it's an IP address.
+	///
+	/// # Errors
+	///
+	/// If `host` can't be resolved, or `AddrNotAvailable` if it has no addresses of `family`.
+	pub fn connect_family(
+		family: IpFamily, host: &str, port: u16, executor: &impl Notifier,
+	) -> io::Result<Self> {
+		let remote = (host, port)
+			.to_socket_addrs()?
+			.find(|remote| family.contains(remote.ip()))
+			.ok_or_else(|| {
+				io::Error::new(
+					io::ErrorKind::AddrNotAvailable,
+					format!("{host} has no {family:?} address"),
+				)
+			})?;
+		Ok(Self::connect(
+			net::SocketAddr::new(family.unspecified(), 0),
+			remote,
+			executor,
+		))
+	}
+	/// Connect to `remote` from `source_ip`, with an ephemeral port, such as to choose which interface of a multihomed host it goes out on.
+	///
+	/// # Errors
+	///
+	/// `InvalidInput` if `source_ip` and `remote` are of different address families.
+	pub fn connect_bind(
+		source_ip: net::IpAddr, remote: net::SocketAddr, executor: &impl Notifier,
+	) -> io::Result<Self> {
+		if source_ip.is_ipv4() != remote.is_ipv4() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("source {source_ip} is of a different address family to remote {remote}"),
+			));
+		}
+		Ok(Self::connect(
+			net::SocketAddr::new(source_ip, 0),
+			remote,
+			executor,
+		))
+	}
+	/// # Panics
+	///
+	/// If a livelock is detected while connecting and [`ConnectionConfig::poll_limit_action`] is to panic.
 	pub fn poll(&mut self, executor: &impl Notifier) {
+		if self.is_terminal() {
+			// polling a connection that should have been dropped is likely a bug in the caller
+			log::trace!("Connection polled after reaching terminal state {self:?}");
+			let _ = TERMINAL_POLLS.fetch_add(1, Ordering::Relaxed);
+			return;
+		}
+		#[cfg(feature = "tracing")]
+		let span = tracing::trace_span!(
+			"connection",
+			remote = %self.remote().unwrap(),
+			label = self.label(),
+		);
+		#[cfg(feature = "tracing")]
+		let _entered = span.enter();
 		*self = match mem::replace(self, Self::Killed) {
 			Self::Connecter(connecter) => connecter.poll(executor).into(),
 			Self::Connectee(connectee) => connectee.poll(executor).into(),
@@ -36,24 +157,190 @@ impl Connection {
 			Self::RemoteClosed(remote_closed) => remote_closed.poll(executor).into(),
 			Self::LocalClosed(local_closed) => local_closed.poll(executor).into(),
 			Self::Closing(closing) => closing.poll(executor).into(),
-			Self::Closed => Self::Closed,
-			Self::Killed => Self::Killed,
+			Self::Reset(reset) => reset.poll(executor).into(),
+			Self::Socks5(socks5) => socks5.poll(executor).into(),
+			Self::Handshaking(handshaking) => handshaking.poll(executor).into(),
+			Self::Closed | Self::Killed => unreachable!(),
 		};
 	}
+	/// Have the connection polled again as soon as possible, whether or not there's been an event. The states don't cache readiness, so the poll re-attempts reads and writes afresh; this resynchronises them after something done to the socket out-of-band, such as through its raw fd, that the edge-triggered [`Notifier`] won't report. Does nothing once [`Closed`](Connection::Closed) or [`Killed`](Connection::Killed).
+	pub fn refresh(&mut self, executor: &impl Notifier) {
+		if !self.is_terminal() {
+			executor.queue();
+		}
+	}
 	#[inline(always)]
 	pub fn connecting(&self) -> bool {
 		match self {
 			Self::Connecter(_)
 			| Self::Connectee(_)
 			| Self::ConnecterLocalClosed(_)
-			| Self::ConnecteeLocalClosed(_) => true,
+			| Self::ConnecteeLocalClosed(_)
+			| Self::Socks5(_)
+			| Self::Handshaking(_) => true,
 			_ => false,
 		}
 	}
+	/// As [`StateInfo::label`]. `None` once closed or killed.
+	#[must_use]
+	#[inline(always)]
+	pub fn label(&self) -> Option<&str> {
+		match self {
+			Self::Connecter(ref connecter) => connecter.label(),
+			Self::Connectee(ref connectee) => connectee.label(),
+			Self::ConnecterLocalClosed(ref connecter_local_closed) => {
+				connecter_local_closed.label()
+			}
+			Self::ConnecteeLocalClosed(ref connectee_local_closed) => {
+				connectee_local_closed.label()
+			}
+			Self::Connected(ref connected) => connected.label(),
+			Self::RemoteClosed(ref remote_closed) => remote_closed.label(),
+			Self::LocalClosed(ref local_closed) => local_closed.label(),
+			Self::Closing(ref closing) => closing.label(),
+			Self::Reset(ref reset) => reset.label(),
+			Self::Socks5(ref socks5) => socks5.label(),
+			Self::Handshaking(ref handshaking) => handshaking.label(),
+			Self::Closed | Self::Killed => None,
+		}
+	}
+	/// As [`StateInfo::time_in_state`], for spotting connections stuck connecting or closing. Polls that leave it in the same state don't reset this. `None` once closed or killed.
+	#[must_use]
+	#[inline(always)]
+	pub fn time_in_state(&self) -> Option<time::Duration> {
+		match self {
+			Self::Connecter(ref connecter) => Some(connecter.time_in_state()),
+			Self::Connectee(ref connectee) => Some(connectee.time_in_state()),
+			Self::ConnecterLocalClosed(ref connecter_local_closed) => {
+				Some(connecter_local_closed.time_in_state())
+			}
+			Self::ConnecteeLocalClosed(ref connectee_local_closed) => {
+				Some(connectee_local_closed.time_in_state())
+			}
+			Self::Connected(ref connected) => Some(connected.time_in_state()),
+			Self::RemoteClosed(ref remote_closed) => Some(remote_closed.time_in_state()),
+			Self::LocalClosed(ref local_closed) => Some(local_closed.time_in_state()),
+			Self::Closing(ref closing) => Some(closing.time_in_state()),
+			Self::Reset(ref reset) => Some(reset.time_in_state()),
+			Self::Socks5(ref socks5) => Some(socks5.time_in_state()),
+			Self::Handshaking(ref handshaking) => Some(handshaking.time_in_state()),
+			Self::Closed | Self::Killed => None,
+		}
+	}
+	/// The remote address. `None` once closed or killed.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> Option<net::SocketAddr> {
+		match self {
+			Self::Connecter(ref connecter) => Some(connecter.remote()),
+			Self::Connectee(ref connectee) => Some(connectee.remote()),
+			Self::ConnecterLocalClosed(ref connecter_local_closed) => {
+				Some(connecter_local_closed.remote())
+			}
+			Self::ConnecteeLocalClosed(ref connectee_local_closed) => {
+				Some(connectee_local_closed.remote())
+			}
+			Self::Connected(ref connected) => Some(connected.remote()),
+			Self::RemoteClosed(ref remote_closed) => Some(remote_closed.remote()),
+			Self::LocalClosed(ref local_closed) => Some(local_closed.remote()),
+			Self::Closing(ref closing) => Some(closing.remote()),
+			Self::Reset(ref reset) => Some(reset.remote()),
+			Self::Socks5(ref socks5) => Some(socks5.remote()),
+			Self::Handshaking(ref handshaking) => Some(handshaking.remote()),
+			Self::Closed | Self::Killed => None,
+		}
+	}
+	/// The destination requested of the SOCKS5 proxy, while connecting through it. [`remote`](Connection::remote) is meanwhile the proxy, and remains so once connected to a [`Socks5Target::Domain`].
+	#[must_use]
+	#[inline(always)]
+	pub fn socks5_target(&self) -> Option<&Socks5Target> {
+		match self {
+			Self::Socks5(ref socks5) => Some(socks5.target()),
+			_ => None,
+		}
+	}
+	/// The remote address the socket is connected to, from `getpeername`, as with [`TcpStream::peer_addr`](std::net::TcpStream::peer_addr). Unlike [`remote`](Connection::remote) this is what the OS reports rather than what was dialed or accepted, so for example it's the proxy for [`Socks5`], or the load balancer for a PROXY protocol connection.
+	///
+	/// # Errors
+	///
+	/// `ENOTCONN` once reset, closed or killed, or while there's no socket, such as between a [`Connecter`]'s attempts.
+	pub fn peer_addr(&self) -> io::Result<net::SocketAddr> {
+		peer_addr(self.fd().ok_or_else(not_connected)?)
+	}
+	/// The local address the socket is bound to, from `getsockname`, as with [`TcpStream::local_addr`](std::net::TcpStream::local_addr).
+	///
+	/// # Errors
+	///
+	/// `ENOTCONN` once reset, closed or killed, or while there's no socket, such as between a [`Connecter`]'s attempts.
+	pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+		local_addr(self.fd().ok_or_else(not_connected)?)
+	}
+	// The socket, if there is one.
+	pub(crate) fn fd(&self) -> Option<Fd> {
+		match self {
+			Self::Connecter(ref connecter) => connecter.fd(),
+			Self::Connectee(ref connectee) => Some(connectee.fd()),
+			Self::ConnecterLocalClosed(ref connecter_local_closed) => connecter_local_closed.fd(),
+			Self::ConnecteeLocalClosed(ref connectee_local_closed) => {
+				Some(connectee_local_closed.fd())
+			}
+			Self::Connected(ref connected) => Some(connected.fd()),
+			Self::RemoteClosed(ref remote_closed) => Some(remote_closed.fd()),
+			Self::LocalClosed(ref local_closed) => Some(local_closed.fd()),
+			Self::Closing(ref closing) => Some(closing.fd()),
+			Self::Socks5(ref socks5) => socks5.fd(),
+			Self::Handshaking(ref handshaking) => Some(handshaking.fd()),
+			Self::Reset(_) | Self::Closed | Self::Killed => None,
+		}
+	}
+	/// As [`StateInfo::origin`]. `None` once closed or killed.
+	#[must_use]
+	#[inline(always)]
+	pub fn origin(&self) -> Option<Origin> {
+		match self {
+			Self::Connecter(ref connecter) => Some(connecter.origin()),
+			Self::Connectee(ref connectee) => Some(connectee.origin()),
+			Self::ConnecterLocalClosed(ref connecter_local_closed) => {
+				Some(connecter_local_closed.origin())
+			}
+			Self::ConnecteeLocalClosed(ref connectee_local_closed) => {
+				Some(connectee_local_closed.origin())
+			}
+			Self::Connected(ref connected) => Some(connected.origin()),
+			Self::RemoteClosed(ref remote_closed) => Some(remote_closed.origin()),
+			Self::LocalClosed(ref local_closed) => Some(local_closed.origin()),
+			Self::Closing(ref closing) => Some(closing.origin()),
+			Self::Reset(ref reset) => Some(reset.origin()),
+			Self::Socks5(ref socks5) => Some(socks5.origin()),
+			Self::Handshaking(ref handshaking) => Some(handshaking.origin()),
+			Self::Closed | Self::Killed => None,
+		}
+	}
+	/// The readiness this is waiting on, as of the last [`poll`](Connection::poll). Nothing once reset, closed or killed.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		match self {
+			Self::Connecter(ref connecter) => connecter.interest(),
+			Self::Connectee(ref connectee) => connectee.interest(),
+			Self::ConnecterLocalClosed(ref connecter_local_closed) => {
+				connecter_local_closed.interest()
+			}
+			Self::ConnecteeLocalClosed(ref connectee_local_closed) => {
+				connectee_local_closed.interest()
+			}
+			Self::Connected(ref connected) => connected.interest(),
+			Self::RemoteClosed(ref remote_closed) => remote_closed.interest(),
+			Self::LocalClosed(ref local_closed) => local_closed.interest(),
+			Self::Closing(ref closing) => closing.interest(),
+			Self::Socks5(ref socks5) => socks5.interest(),
+			Self::Handshaking(ref handshaking) => handshaking.interest(),
+			Self::Reset(_) | Self::Closed | Self::Killed => Interest::default(),
+		}
+	}
 	#[inline(always)]
 	pub fn recvable(&self) -> bool {
 		match self {
-			Self::Connected(_) | Self::LocalClosed(_) => true,
+			Self::Connected(_) | Self::LocalClosed(_) | Self::Reset(_) => true,
 			_ => false,
 		}
 	}
@@ -63,12 +350,23 @@ impl Connection {
 			Some(match self {
 				Self::Connected(ref connected) => connected.recv_avail(),
 				Self::LocalClosed(ref local_closed) => local_closed.recv_avail(),
+				Self::Reset(ref reset) => reset.recv_avail(),
 				_ => unreachable!(),
 			})
 		} else {
 			None
 		}
 	}
+	/// Free space in the receive buffer, for states still reading from the socket.
+	#[must_use]
+	#[inline(always)]
+	pub fn recv_space(&self) -> Option<usize> {
+		match self {
+			Self::Connected(ref connected) => Some(connected.recv_space()),
+			Self::LocalClosed(ref local_closed) => Some(local_closed.recv_space()),
+			_ => None,
+		}
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn recv<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
@@ -79,6 +377,7 @@ impl Connection {
 					Self::LocalClosed(ref mut local_closed) => {
 						local_closed.recv(executor).unwrap()()
 					}
+					Self::Reset(ref mut reset) => reset.recv(executor).unwrap()(),
 					_ => unreachable!(),
 				})
 			} else {
@@ -86,6 +385,15 @@ impl Connection {
 			}
 		})
 	}
+	/// Copy as many received bytes as fit into `buf`, returning how many were copied. 0 if not [`recvable`](Connection::recvable).
+	pub fn recv_slice(&mut self, buf: &mut [u8], executor: &impl Notifier) -> usize {
+		match self {
+			Self::Connected(ref mut connected) => connected.recv_slice(buf, executor),
+			Self::LocalClosed(ref mut local_closed) => local_closed.recv_slice(buf, executor),
+			Self::Reset(ref mut reset) => reset.recv_slice(buf, executor),
+			_ => 0,
+		}
+	}
 	#[inline(always)]
 	pub fn sendable(&self) -> bool {
 		match self {
@@ -107,6 +415,18 @@ impl Connection {
 			None
 		}
 	}
+	/// Bytes in the send buffer not yet written to the socket, for states that have one.
+	#[must_use]
+	#[inline(always)]
+	pub fn send_buffered(&self) -> Option<usize> {
+		match self {
+			Self::Connected(ref connected) => Some(connected.send_buffered()),
+			Self::RemoteClosed(ref remote_closed) => Some(remote_closed.send_buffered()),
+			Self::LocalClosed(ref local_closed) => Some(local_closed.send_buffered()),
+			Self::Closing(ref closing) => Some(closing.send_buffered()),
+			_ => None,
+		}
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn send<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce(u8) + 'a> {
@@ -124,6 +444,14 @@ impl Connection {
 			}
 		})
 	}
+	/// Queue as much of `buf` as fits in the send buffer, returning how many bytes were queued. 0 if not [`sendable`](Connection::sendable).
+	pub fn send_slice(&mut self, buf: &[u8], executor: &impl Notifier) -> usize {
+		match self {
+			Self::Connected(ref mut connected) => connected.send_slice(buf, executor),
+			Self::RemoteClosed(ref mut remote_closed) => remote_closed.send_slice(buf, executor),
+			_ => 0,
+		}
+	}
 	#[inline(always)]
 	pub fn closed(&self) -> bool {
 		match self {
@@ -131,6 +459,15 @@ impl Connection {
 			_ => false,
 		}
 	}
+	/// Whether this is [`Closed`](Connection::Closed) or [`Killed`](Connection::Killed), from which it'll never change and can be dropped.
+	#[must_use]
+	#[inline(always)]
+	pub fn is_terminal(&self) -> bool {
+		match self {
+			Self::Closed | Self::Killed => true,
+			_ => false,
+		}
+	}
 	#[inline(always)]
 	pub fn valid(&self) -> bool {
 		match self {
@@ -142,10 +479,23 @@ impl Connection {
 			| Self::RemoteClosed(_)
 			| Self::LocalClosed(_)
 			| Self::Closing(_)
+			| Self::Reset(_)
+			| Self::Socks5(_)
+			| Self::Handshaking(_)
 			| Self::Closed => true,
 			Self::Killed => false,
 		}
 	}
+	/// Whether [`close`](Connection::close) would leave this [`Closed`](Connection::Closed) without any further polls. `false` if it isn't [`closable`](Connection::closable), or if the close would have to wait on the send buffer or kernel send queue draining, or on the remote closing.
+	#[must_use]
+	#[inline(always)]
+	pub fn close_is_immediate(&self) -> bool {
+		match self {
+			Self::Connected(ref connected) => connected.close_is_immediate(),
+			Self::RemoteClosed(ref remote_closed) => remote_closed.close_is_immediate(),
+			_ => false,
+		}
+	}
 	#[inline(always)]
 	pub fn closable(&self) -> bool {
 		match self {
@@ -157,10 +507,24 @@ impl Connection {
 			| Self::ConnecteeLocalClosed(_)
 			| Self::LocalClosed(_)
 			| Self::Closing(_)
+			| Self::Reset(_)
+			| Self::Socks5(_)
+			| Self::Handshaking(_)
 			| Self::Closed
 			| Self::Killed => false,
 		}
 	}
+	/// Whether the write side has been shut down, so the FIN has been handed to the kernel. Always true once [`Closed`](Connection::Closed); false for states yet to close, and for [`Killed`](Connection::Killed) and [`Reset`](Connection::Reset).
+	#[must_use]
+	#[inline(always)]
+	pub fn local_fin_sent(&self) -> bool {
+		match self {
+			Self::LocalClosed(ref local_closed) => local_closed.local_fin_sent(),
+			Self::Closing(ref closing) => closing.local_fin_sent(),
+			Self::Closed => true,
+			_ => false,
+		}
+	}
 	#[must_use]
 	pub fn close<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
 		if self.closable() {
@@ -177,6 +541,69 @@ impl Connection {
 			None
 		}
 	}
+	/// Like [`close`](Connection::close), but reset the connection if it hasn't closed within `timeout`, overriding [`ConnectionConfig::close_timeout`].
+	#[must_use]
+	pub fn close_with_timeout<'a>(
+		&'a mut self, timeout: time::Duration, executor: &'a impl Notifier,
+	) -> Option<impl FnOnce() + 'a> {
+		if self.closable() {
+			Some(move || {
+				*self = match mem::replace(self, Self::Killed) {
+					Self::Connecter(connecter) => {
+						connecter.close_with_timeout(timeout, executor).into()
+					}
+					Self::Connectee(connectee) => {
+						connectee.close_with_timeout(timeout, executor).into()
+					}
+					Self::Connected(connected) => {
+						connected.close_with_timeout(timeout, executor).into()
+					}
+					Self::RemoteClosed(remote_closed) => {
+						remote_closed.close_with_timeout(timeout, executor).into()
+					}
+					_ => unreachable!(),
+				};
+			})
+		} else {
+			None
+		}
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn handshakeable(&self) -> bool {
+		match self {
+			Self::Connectee(_) | Self::Connected(_) => true,
+			_ => false,
+		}
+	}
+	/// Run a handshake before this becomes, or is again, [`Connected`](Connection::Connected); see [`Handshaking`].
+	#[must_use]
+	pub fn handshake<'a>(
+		&'a mut self, executor: &'a impl Notifier,
+	) -> Option<impl FnOnce(Handshake) + 'a> {
+		if self.handshakeable() {
+			Some(move |handshake| {
+				*self = match mem::replace(self, Self::Killed) {
+					Self::Connectee(connectee) => connectee.handshake(handshake, executor).into(),
+					Self::Connected(connected) => connected.handshake(handshake, executor).into(),
+					_ => unreachable!(),
+				};
+			})
+		} else {
+			None
+		}
+	}
+	/// Whether this must be consumed, by [`close`](Connection::close) and polling until [`Closed`](Connection::Closed) or by [`kill`](Connection::kill), before being dropped; i.e. whether dropping it would panic.
+	///
+	/// The recommended pattern for cleanup code, such as in `Drop` or on an error path, is:
+	/// * where there's time to, [`close`](Connection::close) the connection and keep polling it until `must_consume` is false, so buffered data is delivered;
+	/// * otherwise, or if that hangs, [`kill`](Connection::kill) it;
+	/// * where a connection might be dropped before either, for example by `?` or unwinding, wrap it in a [`ConnectionGuard`], which kills it on drop, and with debug assertions warns if that wasn't during unwinding.
+	#[must_use]
+	#[inline(always)]
+	pub fn must_consume(&self) -> bool {
+		self.killable()
+	}
 	#[inline(always)]
 	pub fn killable(&self) -> bool {
 		match self {
@@ -187,34 +614,301 @@ impl Connection {
 			| Self::Connected(_)
 			| Self::RemoteClosed(_)
 			| Self::LocalClosed(_)
-			| Self::Closing(_) => true,
+			| Self::Closing(_)
+			| Self::Reset(_)
+			| Self::Socks5(_)
+			| Self::Handshaking(_) => true,
 			Self::Closed | Self::Killed => false,
 		}
 	}
 	#[must_use]
 	pub fn kill<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
 		if self.killable() {
-			Some(move || {
-				match mem::replace(self, Self::Killed) {
-					Self::Connecter(connecter) => connecter.kill(executor),
-					Self::Connectee(connectee) => connectee.kill(executor),
-					Self::Connected(connected) => connected.kill(executor),
-					Self::RemoteClosed(remote_closed) => remote_closed.kill(executor),
-					Self::LocalClosed(local_closed) => local_closed.kill(executor),
-					Self::ConnecterLocalClosed(connecter_local_closed) => {
-						connecter_local_closed.kill(executor)
-					}
-					Self::ConnecteeLocalClosed(connectee_local_closed) => {
-						connectee_local_closed.kill(executor)
-					}
-					Self::Closing(closing) => closing.kill(executor),
-					_ => unreachable!(),
-				};
+			Some(move || match mem::replace(self, Self::Killed) {
+				Self::Connecter(connecter) => connecter.kill(executor),
+				Self::Connectee(connectee) => connectee.kill(executor),
+				Self::Connected(connected) => connected.kill(executor),
+				Self::RemoteClosed(remote_closed) => remote_closed.kill(executor),
+				Self::LocalClosed(local_closed) => local_closed.kill(executor),
+				Self::ConnecterLocalClosed(connecter_local_closed) => {
+					connecter_local_closed.kill(executor)
+				}
+				Self::ConnecteeLocalClosed(connectee_local_closed) => {
+					connectee_local_closed.kill(executor)
+				}
+				Self::Closing(closing) => closing.kill(executor),
+				Self::Reset(reset) => reset.kill(executor),
+				Self::Socks5(socks5) => socks5.kill(executor),
+				Self::Handshaking(handshaking) => handshaking.kill(executor),
+				_ => unreachable!(),
 			})
 		} else {
 			None
 		}
 	}
+	/// Like [`kill`](Connection::kill), but aborting the connection with a RST, by closing with a zero `SO_LINGER`, so the remote learns promptly that it's been abandoned rather than closed, and no unsent data lingers in the kernel.
+	#[must_use]
+	pub fn reset<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() + 'a> {
+		let fd = self.fd();
+		self.kill(executor).map(|kill| {
+			move || {
+				if let Some(fd) = fd {
+					set_reset_on_close(fd);
+				}
+				kill();
+			}
+		})
+	}
+}
+
+/// An IP address family, as given to [`Connection::connect_family`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IpFamily {
+	V4,
+	V6,
+}
+impl IpFamily {
+	fn contains(self, ip: net::IpAddr) -> bool {
+		matches!(
+			(self, ip),
+			(Self::V4, net::IpAddr::V4(_)) | (Self::V6, net::IpAddr::V6(_))
+		)
+	}
+	fn unspecified(self) -> net::IpAddr {
+		match self {
+			Self::V4 => net::Ipv4Addr::UNSPECIFIED.into(),
+			Self::V6 => net::Ipv6Addr::UNSPECIFIED.into(),
+		}
+	}
+}
+
+/// Wraps a [`Connection`], killing it on drop rather than panicking.
+pub struct ConnectionGuard<'a, N: Notifier> {
+	connection: Connection,
+	executor: &'a N,
+}
+impl<'a, N: Notifier> ConnectionGuard<'a, N> {
+	pub fn new(connection: Connection, executor: &'a N) -> Self {
+		Self {
+			connection,
+			executor,
+		}
+	}
+	#[must_use]
+	pub fn into_inner(mut self) -> Connection {
+		mem::replace(&mut self.connection, Connection::Killed)
+	}
+}
+impl<N: Notifier> ops::Deref for ConnectionGuard<'_, N> {
+	type Target = Connection;
+	fn deref(&self) -> &Connection {
+		&self.connection
+	}
+}
+impl<N: Notifier> ops::DerefMut for ConnectionGuard<'_, N> {
+	fn deref_mut(&mut self) -> &mut Connection {
+		&mut self.connection
+	}
+}
+impl<N: Notifier> Drop for ConnectionGuard<'_, N> {
+	fn drop(&mut self) {
+		// outside of unwinding, the caller likely meant to close or kill it themselves
+		#[cfg(debug_assertions)]
+		if self.connection.must_consume() && !std::thread::panicking() {
+			log::warn!("ConnectionGuard killing unconsumed {:?}", self.connection);
+		}
+		if let Some(kill) = self.connection.kill(self.executor) {
+			kill();
+		}
+	}
+}
+impl<N: Notifier> fmt::Debug for ConnectionGuard<'_, N> {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_tuple("ConnectionGuard")
+			.field(&self.connection)
+			.finish()
+	}
+}
+
+/// Owns many [`Connection`]s, killing any still live on drop rather than panicking, such that an early return or unwind out of a function holding them is safe.
+pub struct ConnectionSet<'a, N: Notifier> {
+	connections: Vec<Connection>,
+	executor: &'a N,
+}
+impl<'a, N: Notifier> ConnectionSet<'a, N> {
+	pub fn new(executor: &'a N) -> Self {
+		Self {
+			connections: Vec::new(),
+			executor,
+		}
+	}
+	/// Add a connection, returning its index. Indices are stable until [`remove`](ConnectionSet::remove) or [`retain`](ConnectionSet::retain).
+	pub fn push(&mut self, connection: Connection) -> usize {
+		self.connections.push(connection);
+		self.connections.len() - 1
+	}
+	/// Take a connection back out, shifting those after it down by one.
+	pub fn remove(&mut self, index: usize) -> Connection {
+		self.connections.remove(index)
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn get(&self, index: usize) -> Option<&Connection> {
+		self.connections.get(index)
+	}
+	#[inline(always)]
+	pub fn get_mut(&mut self, index: usize) -> Option<&mut Connection> {
+		self.connections.get_mut(index)
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn len(&self) -> usize {
+		self.connections.len()
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn is_empty(&self) -> bool {
+		self.connections.is_empty()
+	}
+	pub fn iter(&self) -> impl Iterator<Item = &Connection> {
+		self.connections.iter()
+	}
+	pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Connection> {
+		self.connections.iter_mut()
+	}
+	/// Poll each connection that isn't yet [`Closed`](Connection::Closed) or [`Killed`](Connection::Killed).
+	pub fn poll(&mut self) {
+		for connection in &mut self.connections {
+			if !connection.is_terminal() {
+				connection.poll(self.executor);
+			}
+		}
+	}
+	/// Keep only the connections for which `f` returns `true`, killing the others.
+	pub fn retain(&mut self, mut f: impl FnMut(&Connection) -> bool) {
+		let executor = self.executor;
+		self.connections.retain_mut(|connection| {
+			let keep = f(connection);
+			if !keep {
+				if let Some(kill) = connection.kill(executor) {
+					kill();
+				}
+			}
+			keep
+		});
+	}
+	#[must_use]
+	pub fn into_inner(mut self) -> Vec<Connection> {
+		mem::take(&mut self.connections)
+	}
+}
+impl<N: Notifier> Drop for ConnectionSet<'_, N> {
+	fn drop(&mut self) {
+		for connection in &mut self.connections {
+			if let Some(kill) = connection.kill(self.executor) {
+				kill();
+			}
+		}
+	}
+}
+impl<N: Notifier> fmt::Debug for ConnectionSet<'_, N> {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_tuple("ConnectionSet")
+			.field(&self.connections)
+			.finish()
+	}
+}
+
+/// Wraps a [`Connection`] with per-connection application state, so that it needn't be looked up in a separate map on each event. Derefs to the [`Connection`], so every [`Connection`] method can be called on it directly.
+#[derive(Debug)]
+pub struct ConnectionWith<T> {
+	connection: Connection,
+	user_data: T,
+}
+impl<T> ConnectionWith<T> {
+	pub fn new(connection: Connection, user_data: T) -> Self {
+		Self {
+			connection,
+			user_data,
+		}
+	}
+	#[inline(always)]
+	pub fn user_data(&self) -> &T {
+		&self.user_data
+	}
+	#[inline(always)]
+	pub fn user_data_mut(&mut self) -> &mut T {
+		&mut self.user_data
+	}
+	/// The connection and its user data, mutably at once.
+	#[inline(always)]
+	pub fn split_mut(&mut self) -> (&mut Connection, &mut T) {
+		(&mut self.connection, &mut self.user_data)
+	}
+	pub fn into_inner(self) -> (Connection, T) {
+		(self.connection, self.user_data)
+	}
+}
+impl<T> ops::Deref for ConnectionWith<T> {
+	type Target = Connection;
+	fn deref(&self) -> &Connection {
+		&self.connection
+	}
+}
+impl<T> ops::DerefMut for ConnectionWith<T> {
+	fn deref_mut(&mut self) -> &mut Connection {
+		&mut self.connection
+	}
+}
+
+impl From<Reset> for Connection {
+	#[inline(always)]
+	fn from(reset: Reset) -> Self {
+		Self::Reset(reset)
+	}
+}
+impl From<ResetPoll> for Connection {
+	#[inline(always)]
+	fn from(reset_poll: ResetPoll) -> Self {
+		match reset_poll {
+			ResetPoll::Reset(reset) => Self::Reset(reset),
+			ResetPoll::Killed => Self::Killed,
+		}
+	}
+}
+impl From<Handshaking> for Connection {
+	#[inline(always)]
+	fn from(handshaking: Handshaking) -> Self {
+		Self::Handshaking(handshaking)
+	}
+}
+impl From<HandshakingPoll> for Connection {
+	#[inline(always)]
+	fn from(handshaking_poll: HandshakingPoll) -> Self {
+		match handshaking_poll {
+			HandshakingPoll::Handshaking(handshaking) => Self::Handshaking(handshaking),
+			HandshakingPoll::Connected(connected) => Self::Connected(connected),
+			HandshakingPoll::RemoteClosed(remote_closed) => Self::RemoteClosed(remote_closed),
+			HandshakingPoll::Reset(reset) => Self::Reset(reset),
+			HandshakingPoll::Killed => Self::Killed,
+		}
+	}
+}
+impl From<Socks5> for Connection {
+	#[inline(always)]
+	fn from(socks5: Socks5) -> Self {
+		Self::Socks5(socks5)
+	}
+}
+impl From<Socks5Poll> for Connection {
+	#[inline(always)]
+	fn from(socks5_poll: Socks5Poll) -> Self {
+		match socks5_poll {
+			Socks5Poll::Socks5(socks5) => Self::Socks5(socks5),
+			Socks5Poll::Connected(connected) => Self::Connected(connected),
+			Socks5Poll::Killed => Self::Killed,
+		}
+	}
 }
 impl From<Connecter> for Connection {
 	#[inline(always)]
@@ -302,6 +996,7 @@ impl From<ConnectedPoll> for Connection {
 		match connected_poll {
 			ConnectedPoll::Connected(connected) => Self::Connected(connected),
 			ConnectedPoll::RemoteClosed(remote_closed) => Self::RemoteClosed(remote_closed),
+			ConnectedPoll::Reset(reset) => Self::Reset(reset),
 			ConnectedPoll::Killed => Self::Killed,
 		}
 	}
@@ -333,6 +1028,7 @@ impl From<LocalClosedPoll> for Connection {
 		match local_closed_poll {
 			LocalClosedPoll::LocalClosed(local_closed) => Self::LocalClosed(local_closed),
 			LocalClosedPoll::Closing(closing) => Self::Closing(closing),
+			LocalClosedPoll::Reset(reset) => Self::Reset(reset),
 			LocalClosedPoll::Closed => Self::Closed,
 			LocalClosedPoll::Killed => Self::Killed,
 		}
@@ -354,3 +1050,7 @@ impl From<ClosingPoll> for Connection {
 		}
 	}
 }
+
+fn not_connected() -> io::Error {
+	io::Error::from_raw_os_error(nix::libc::ENOTCONN)
+}
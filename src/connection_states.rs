@@ -3,55 +3,205 @@ use circular_buffer::CircularBuffer;
 use log::trace;
 #[cfg(unix)]
 use nix::{errno, fcntl, libc, sys::socket, unistd};
-use std::{mem, net, time};
+use std::{
+	cmp,
+	convert::TryInto,
+	fs, io, mem, net,
+	os::unix::io::{AsRawFd, IntoRawFd},
+	time,
+};
 
+/// What a [`Listener`]'s accept hook decides to do with a newly accepted connection.
+pub enum AcceptDecision {
+	/// Accept it, yielding a [`Connectee`].
+	Accept,
+	/// Forward it to the process at the other end of the [`SocketForwarder`]. It's sent with a payload marking it as unread, by which a receiving [`Listener`] on macOS and iOS checks it arrived intact.
+	Forward(SocketForwarder),
+	/// Reject it, resetting the connection.
+	Reject,
+}
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct Listener {
 	fd: Fd,
 	is_socket_forwarder: bool,
+	// the file of a named SocketForwardee, removed once it's replaced by the forwarded listener, or this is closed
+	forwardee_path: Option<SocketPath>,
+	// whether the listening socket was created here, so that accepted sockets, which inherit its options, start with the defaults
+	own_socket: bool,
+	proxy_protocol: bool,
+	// set by pause, while which the fd isn't registered with the Notifier
+	paused: bool,
+	config: ConnectionConfig,
 }
 impl Listener {
-	pub fn new_ephemeral(host: &net::IpAddr, executor: &impl Notifier) -> (Self, u16) {
+	/// Listen on an ephemeral port of `host`, returning the listener and the port.
+	///
+	/// # Errors
+	///
+	/// If the socket can't be created, bound or set up.
+	pub fn new_ephemeral(host: &net::IpAddr, executor: &impl Notifier) -> io::Result<(Self, u16)> {
+		Self::new_ephemeral_with(host, &ListenerConfig::default(), executor)
+	}
+	/// # Errors
+	///
+	/// If the socket can't be created, bound or set up.
+	///
+	/// # Panics
+	///
+	/// If `config.connection` is invalid; see [`ConnectionConfig`].
+	pub fn new_ephemeral_with(
+		host: &net::IpAddr, config: &ListenerConfig, executor: &impl Notifier,
+	) -> io::Result<(Self, u16)> {
+		Self::new(&net::SocketAddr::new(*host, 0), config, false, executor)
+	}
+	/// Listen on exactly `addr`, returning the error rather than panicking if it can't be bound, such as `EADDRINUSE` or `EACCES`.
+	///
+	/// # Errors
+	///
+	/// If `addr` can't be bound, or the socket can't be created or set up.
+	pub fn bind(addr: &net::SocketAddr, executor: &impl Notifier) -> io::Result<Self> {
+		Self::bind_with(addr, &ListenerConfig::default(), executor)
+	}
+	/// # Errors
+	///
+	/// If `addr` can't be bound, or the socket can't be created or set up.
+	///
+	/// # Panics
+	///
+	/// If `config.connection` is invalid; see [`ConnectionConfig`].
+	pub fn bind_with(
+		addr: &net::SocketAddr, config: &ListenerConfig, executor: &impl Notifier,
+	) -> io::Result<Self> {
+		Self::new(addr, config, false, executor).map(|(listener, _port)| listener)
+	}
+	/// Listen on exactly `addr`, alongside any other listeners bound to it this way (`SO_REUSEPORT`), for instance one per thread, such that the kernel spreads incoming connections between them. This load balancing is Linux's behaviour; elsewhere the binding succeeds but connections may all go to one listener.
+	///
+	/// Connections are assigned to the listeners bound at the time they arrive, so all should be bound before the first connection for an even distribution.
+	///
+	/// # Errors
+	///
+	/// If `addr` can't be bound, or the socket can't be created or set up.
+	pub fn new_shared(addr: &net::SocketAddr, executor: &impl Notifier) -> io::Result<Self> {
+		Self::new_shared_with(addr, &ListenerConfig::default(), executor)
+	}
+	/// # Errors
+	///
+	/// If `addr` can't be bound, or the socket can't be created or set up.
+	///
+	/// # Panics
+	///
+	/// If `config.connection` is invalid; see [`ConnectionConfig`].
+	pub fn new_shared_with(
+		addr: &net::SocketAddr, config: &ListenerConfig, executor: &impl Notifier,
+	) -> io::Result<Self> {
+		Self::new(addr, config, true, executor).map(|(listener, _port)| listener)
+	}
+	// `shared` sets SO_REUSEPORT before binding rather than after, so that others can bind the same address.
+	fn new(
+		addr: &net::SocketAddr, config: &ListenerConfig, shared: bool, executor: &impl Notifier,
+	) -> io::Result<(Self, u16)> {
+		config.connection.validate();
 		let process_listener = palaver::socket::socket(
-			socket::AddressFamily::Inet,
+			address_family(addr),
 			socket::SockType::Stream,
 			palaver::socket::SockFlag::SOCK_NONBLOCK,
 			socket::SockProtocol::Tcp,
 		)
-		.unwrap();
-		socket::setsockopt(process_listener, socket::sockopt::ReuseAddr, &true).unwrap();
-		socket::bind(
-			process_listener,
-			&socket::SockAddr::Inet(socket::InetAddr::from_std(&net::SocketAddr::new(*host, 0))),
-		)
-		.unwrap();
-		socket::setsockopt(process_listener, socket::sockopt::ReusePort, &true).unwrap();
-		let process_id =
-			if let socket::SockAddr::Inet(inet) = socket::getsockname(process_listener).unwrap() {
-				inet.to_std()
-			} else {
-				panic!()
+		.map_err(nix_to_io)?;
+		let port = (|| {
+			socket::setsockopt(process_listener, socket::sockopt::ReuseAddr, &true)?;
+			if addr.is_ipv6() {
+				sockopt::set(
+					process_listener,
+					libc::IPPROTO_IPV6,
+					libc::IPV6_V6ONLY,
+					libc::c_int::from(config.ipv6_only),
+				)?;
+			}
+			#[cfg(target_os = "linux")]
+			if let Some(cpu) = config.incoming_cpu {
+				let cpu: libc::c_int = cpu
+					.try_into()
+					.map_err(|_| nix::Error::Sys(errno::Errno::EINVAL))?;
+				sockopt::set(
+					process_listener,
+					libc::SOL_SOCKET,
+					libc::SO_INCOMING_CPU,
+					cpu,
+				)?;
+			}
+			if shared {
+				socket::setsockopt(process_listener, socket::sockopt::ReusePort, &true)?;
+			}
+			socket::bind(
+				process_listener,
+				&socket::SockAddr::Inet(socket::InetAddr::from_std(addr)),
+			)?;
+			if !shared {
+				socket::setsockopt(process_listener, socket::sockopt::ReusePort, &true)?;
 			}
-			.port();
+			let port = inet_addr(socket::getsockname(process_listener)?)?.port();
+			socket::listen(process_listener, LISTEN_BACKLOG)?;
+			Ok::<_, nix::Error>(port)
+		})()
+		.inspect_err(|err| {
+			trace!("Listener bind {addr} err {err:?}");
+			unistd::close(process_listener).unwrap();
+		})
+		.map_err(nix_to_io)?;
 		executor.add_fd(process_listener);
-		socket::listen(process_listener, LISTEN_BACKLOG).unwrap();
-		(
+		Ok((
 			Self {
 				fd: process_listener,
 				is_socket_forwarder: false,
+				forwardee_path: None,
+				own_socket: true,
+				proxy_protocol: config.proxy_protocol,
+				paused: false,
+				config: config.connection.clone(),
 			},
-			process_id,
-		)
-	}
-	pub fn with_fd(process_listener: Fd, executor: &impl Notifier) -> Self {
+			port,
+		))
+	}
+	/// Take over the listening `listener`, making it non-blocking.
+	///
+	/// # Errors
+	///
+	/// If it can't be made non-blocking, or `listen` fails, in which case it's closed.
+	///
+	/// # Panics
+	///
+	/// If closing the socket after an error fails.
+	pub fn from_std(listener: net::TcpListener, executor: &impl Notifier) -> io::Result<Self> {
+		let fd = listener.into_raw_fd();
+		set_nonblocking(fd)
+			.map_err(nix_to_io)
+			.and_then(|()| Self::with_fd(fd, executor))
+			.inspect_err(|_| unistd::close(fd).unwrap())
+	}
+	/// Listen on the bound socket `process_listener`. If `listen` fails, the socket remains the caller's to close.
+	///
+	/// # Errors
+	///
+	/// If `listen` fails.
+	pub fn with_fd(process_listener: Fd, executor: &impl Notifier) -> io::Result<Self> {
+		socket::listen(process_listener, LISTEN_BACKLOG).map_err(nix_to_io)?;
 		executor.add_fd(process_listener);
-		socket::listen(process_listener, LISTEN_BACKLOG).unwrap();
-		Self {
+		Ok(Self {
 			fd: process_listener,
 			is_socket_forwarder: false,
-		}
+			forwardee_path: None,
+			own_socket: false,
+			proxy_protocol: false,
+			paused: false,
+			config: ConnectionConfig::default(),
+		})
 	}
-	pub fn into_fd(self) -> Fd {
+	pub fn into_fd(mut self) -> Fd {
 		let ret = self.fd;
+		let _ = self.forwardee_path.take();
+		let _ = mem::take(&mut self.config);
 		mem::forget(self);
 		ret
 	}
@@ -62,12 +212,21 @@ impl Listener {
 		Self {
 			fd: socket_forwardee.0,
 			is_socket_forwarder: true,
+			forwardee_path: socket_forwardee.1,
+			own_socket: false,
+			proxy_protocol: false,
+			paused: false,
+			config: ConnectionConfig::default(),
 		}
 	}
-	pub fn poll<'a, F: FnMut(&Fd) -> Option<SocketForwarder>, E: Notifier>(
+	#[allow(clippy::too_many_lines)]
+	pub fn poll<'a, F: FnMut(&Fd, net::SocketAddr) -> AcceptDecision, E: Notifier>(
 		&'a mut self, executor: &'a E, accept_hook: &'a mut F,
 	) -> impl Iterator<Item = (net::SocketAddr, impl FnOnce(&E) -> ConnecteePoll)> + 'a {
 		itertools::unfold((), move |_| {
+			if self.paused {
+				return None;
+			}
 			loop {
 				let fd = if !self.is_socket_forwarder {
 					palaver::socket::accept(
@@ -75,80 +234,98 @@ impl Listener {
 						palaver::socket::SockFlag::SOCK_CLOEXEC
 							| palaver::socket::SockFlag::SOCK_NONBLOCK,
 					)
+					.map(|fd| (fd, false))
 				} else {
-					SocketForwardee(self.fd).recv().and_then(|fd| {
-						match palaver::socket::accept(
-							fd,
-							palaver::socket::SockFlag::SOCK_CLOEXEC
-								| palaver::socket::SockFlag::SOCK_NONBLOCK,
-						) {
-							// alternative but doesn't work on mac: socket::getsockopt(fd, socket::sockopt::AcceptConn).unwrap()
-							Err(nix::Error::Sys(errno::Errno::EINVAL)) => Ok(fd),
-							x => {
-								trace!("Listener received forwarded listener");
-								assert!(self.is_socket_forwarder);
-								executor.remove_fd(self.fd);
-								unistd::close(self.fd).unwrap();
-								assert!(
-									fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFL).unwrap()
-										& fcntl::OFlag::O_NONBLOCK.bits() != 0
-								);
-								executor.add_fd(fd);
-								self.fd = fd;
-								self.is_socket_forwarder = false;
-								x
+					SocketForwardee(self.fd, None)
+						.recv_forward()
+						.and_then(|(fd, payload)| {
+							// the sender may not have set it, and without it the accept below would block on a forwarded listener. This also affects any copy the sender kept
+							let nonblocking = fd_flags(fd).and_then(|flags| {
+								if flags.contains(fcntl::OFlag::O_NONBLOCK) {
+									return Ok(());
+								}
+								trace!("Listener received blocking fd");
+								set_nonblocking(fd)
+							});
+							if let Err(err) = nonblocking {
+								trace!("Listener couldn't set received fd nonblocking {err:?}");
+								let _ = unistd::close(fd);
+								// skipped, as for a malformed forward
+								return Err(nix::Error::Sys(errno::Errno::EBADMSG));
 							}
-						}
-					})
+							debug_assert!(fd_flags(fd)
+								.is_ok_and(|flags| flags.contains(fcntl::OFlag::O_NONBLOCK)));
+							match palaver::socket::accept(
+								fd,
+								palaver::socket::SockFlag::SOCK_CLOEXEC
+									| palaver::socket::SockFlag::SOCK_NONBLOCK,
+							) {
+								// alternative but doesn't work on mac: socket::getsockopt(fd, socket::sockopt::AcceptConn).unwrap()
+								Err(nix::Error::Sys(errno::Errno::EINVAL)) => {
+									Ok((fd, payload == FORWARD_UNREAD))
+								}
+								x => {
+									trace!("Listener received forwarded listener");
+									assert!(self.is_socket_forwarder);
+									executor.remove_fd(self.fd);
+									unistd::close(self.fd).unwrap();
+									self.forwardee_path = None;
+									executor.add_fd(fd);
+									self.fd = fd;
+									self.is_socket_forwarder = false;
+									x.map(|fd| (fd, false))
+								}
+							}
+						})
 				};
 				match fd {
-					Ok(fd) => {
-						match accept_hook(&fd) {
-							None => {
-								if let (Ok(remote), 0) = (
-									socket::getpeername(fd),
-									socket::getsockopt(fd, socket::sockopt::SocketError).unwrap(),
+					Ok((fd, unread)) => {
+						let Ok(remote) = socket::getpeername(fd).and_then(inet_addr) else {
+							unistd::close(fd).unwrap();
+							trace!("Listener !accepted");
+							continue;
+						};
+						let remote = unmap_remote(remote);
+						// a socket forwarded by a Listener straight from accept hasn't been read, so anything received should still be readable; if not, it's likely been corrupted in flight, so reset it promptly rather than leave it to stall. This is a heuristic, and the sender's delayed close remains the mitigation
+						#[cfg(any(target_os = "macos", target_os = "ios"))]
+						if unread && sockstate::rx_unreadable(fd) {
+							trace!(
+								"Listener received corrupted forward {}",
+								format_remote(remote, &self.config)
+							);
+							set_reset_on_close(fd);
+							unistd::close(fd).unwrap();
+							continue;
+						}
+						#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+						let _ = unread;
+						match accept_hook(&fd, remote) {
+							AcceptDecision::Accept => {
+								if let (Ok(0), Ok(())) = (
+									socket::getsockopt(fd, socket::sockopt::SocketError),
+									// SO_REUSEADDR and SO_REUSEPORT only affect bind, so aren't set on accepted sockets
+									set_connection_sockopts(fd, &self.config, self.own_socket),
 								) {
-									let remote = if let socket::SockAddr::Inet(inet) = remote {
-										inet.to_std()
-									} else {
-										panic!()
-									};
-									socket::setsockopt(fd, socket::sockopt::ReusePort, &true)
-										.unwrap();
-									socket::setsockopt(fd, socket::sockopt::ReuseAddr, &true)
-										.unwrap();
-									socket::setsockopt(
-										fd,
-										socket::sockopt::Linger,
-										&libc::linger {
-											l_onoff: 1,
-											l_linger: 10,
-										},
-									)
-									.unwrap(); // assert that close is quick?? https://www.nybek.com/blog/2015/04/29/so_linger-on-non-blocking-sockets/
-									socket::setsockopt(fd, socket::sockopt::TcpNoDelay, &true)
-										.unwrap();
-									trace!("Listener accepted {}", format_remote(remote));
+									trace!(
+										"Listener accepted {}",
+										format_remote(remote, &self.config)
+									);
+									let config = self.config.clone();
+									let proxy_protocol = self.proxy_protocol;
 									return Some((
 										remote,
 										(move |executor: &E| {
-											let connectee = Connectee::new(fd, executor, remote);
-											match &connectee {
-												ConnecteePoll::Connectee(Connectee {
-													fd, ..
-												})
-												| ConnecteePoll::Connected(Connected {
-													fd, ..
-												})
-												| ConnecteePoll::RemoteClosed(RemoteClosed {
-													fd,
-													..
-												}) => {
-													executor.queue();
-													executor.add_fd(*fd);
-												}
-												ConnecteePoll::Killed => (),
+											// registered before polling, as killing it on error deregisters it
+											executor.add_fd(fd);
+											let connectee = Connectee::new(
+												fd,
+												config,
+												proxy_protocol,
+												executor,
+												remote,
+											);
+											if !matches!(connectee, ConnecteePoll::Killed) {
+												executor.queue();
 											}
 											connectee
 										}),
@@ -158,20 +335,113 @@ impl Listener {
 									trace!("Listener !accepted");
 								}
 							}
-							Some(to) => {
-								to.send(fd, false).unwrap();
+							AcceptDecision::Forward(to) => {
+								if let Err(err) = to.send_with(fd, false, FORWARD_UNREAD) {
+									trace!("Listener forward failed {:?}", err.error);
+									err.reset();
+								}
+							}
+							AcceptDecision::Reject => {
+								trace!("Listener rejected {}", format_remote(remote, &self.config));
+								set_reset_on_close(fd);
+								unistd::close(fd).unwrap();
 							}
 						}
 					}
 					Err(nix::Error::Sys(errno::Errno::EAGAIN)) => return None,
-					Err(err) => panic!("Listener err {:?} {:?}", self.is_socket_forwarder, err,),
+					Err(nix::Error::Sys(errno::Errno::EBADMSG)) if self.is_socket_forwarder => {
+						trace!("Listener received malformed forward");
+					}
+					// the connection was reset while in the accept queue
+					Err(nix::Error::Sys(errno::Errno::ECONNABORTED | errno::Errno::EPROTO)) => (),
+					// out of fds or memory; leave the rest queued until the next poll, by when some may have been freed
+					Err(
+						err @ nix::Error::Sys(
+							errno::Errno::EMFILE
+							| errno::Errno::ENFILE
+							| errno::Errno::ENOBUFS
+							| errno::Errno::ENOMEM,
+						),
+					) => {
+						trace!("Listener accept err {err:?}");
+						return None;
+					}
+					Err(err) => panic!("Listener err {:?} {:?}", self.is_socket_forwarder, err),
 				}
 			}
 		})
 	}
-	pub fn close(self, executor: &impl Notifier) {
-		executor.remove_fd(self.fd);
+	/// Accept all pending connections, as with [`poll`](Listener::poll), constructing each connectee immediately.
+	pub fn accept_all<F: FnMut(&Fd, net::SocketAddr) -> AcceptDecision, E: Notifier>(
+		&mut self, executor: &E, accept_hook: &mut F,
+	) -> Vec<Connection> {
+		self.poll(executor, accept_hook)
+			.map(|(_remote, connectee)| connectee(executor).into())
+			.collect()
+	}
+	/// Stop accepting, for load shedding, without closing the listening socket: it's deregistered from the `executor`, and [`poll`](Listener::poll) yields nothing until [`resume`](Listener::resume). New connections meanwhile queue in the kernel's backlog, beyond which they're dropped or refused per the OS.
+	pub fn pause(&mut self, executor: &impl Notifier) {
+		if !self.paused {
+			trace!("Listener paused");
+			executor.remove_fd(self.fd);
+			self.paused = true;
+		}
+	}
+	/// Resume accepting after [`pause`](Listener::pause), queueing a poll to accept what's built up in the backlog.
+	pub fn resume(&mut self, executor: &impl Notifier) {
+		if self.paused {
+			trace!("Listener resumed");
+			executor.add_fd(self.fd);
+			executor.queue();
+			self.paused = false;
+		}
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+	/// The number of connections that have completed the handshake and are waiting in the kernel's backlog to be accepted, for shedding load before it's been accepted. Linux and Android only; `ENOSYS` elsewhere.
+	///
+	/// # Errors
+	///
+	/// If `TCP_INFO` can't be read, or `ENOSYS` where it isn't supported.
+	pub fn pending(&self) -> io::Result<usize> {
+		#[cfg(any(target_os = "android", target_os = "linux"))]
+		{
+			tcpinfo::accept_queue(self.fd)
+				.map(|pending| pending as usize)
+				.map_err(nix_to_io)
+		}
+		#[cfg(not(any(target_os = "android", target_os = "linux")))]
+		{
+			Err(nix_to_io(nix::Error::Sys(errno::Errno::ENOSYS)))
+		}
+	}
+	/// The listening socket's file status flags (`F_GETFL`), such as `O_NONBLOCK`, for diagnosing how it's been set up.
+	///
+	/// # Errors
+	///
+	/// If `fcntl` fails.
+	pub fn fd_flags(&self) -> io::Result<fcntl::OFlag> {
+		fd_flags(self.fd).map_err(nix_to_io)
+	}
+	/// Close, as with [`close`](Listener::close), but first accept the connections that have completed their handshake and are waiting in the backlog, rather than abandoning them to be reset. This happens even if [paused](Listener::pause). Connections arriving after the backlog is drained are still refused.
+	pub fn close_graceful<E: Notifier>(mut self, executor: &E) -> Vec<Connection> {
+		let paused = mem::replace(&mut self.paused, false);
+		let connections = self.accept_all(executor, &mut |_, _| AcceptDecision::Accept);
+		trace!("Listener close_graceful accepted {}", connections.len());
+		self.paused = paused;
+		self.close(executor);
+		connections
+	}
+	pub fn close(mut self, executor: &impl Notifier) {
+		if !self.paused {
+			executor.remove_fd(self.fd);
+		}
 		unistd::close(self.fd).unwrap();
+		let _ = self.forwardee_path.take();
+		let _ = mem::take(&mut self.config);
 		mem::forget(self);
 	}
 }
@@ -186,9 +456,136 @@ impl fmt::Debug for Listener {
 			.field("fd", &self.fd)
 			.field("socket", &socketstat::socketstat(self.fd))
 			.field("is_socket_forwarder", &self.is_socket_forwarder)
+			.field("forwardee_path", &self.forwardee_path)
+			.field("own_socket", &self.own_socket)
+			.field("proxy_protocol", &self.proxy_protocol)
+			.field("paused", &self.paused)
+			.field("config", &self.config)
 			.finish()
 	}
 }
+// Write as much of `buf` to the socket as it'll take, once what's in `send` is flushed so as to preserve ordering. Errors are left for poll to encounter.
+fn send_direct(fd: Fd, send: &mut CircularBuffer<u8>, buf: &[u8]) -> usize {
+	if send.read_to_fd(fd).is_err() || send.read_available() > 0 {
+		return 0;
+	}
+	let mut written = 0;
+	while written < buf.len() {
+		match socket::send(fd, &buf[written..], socket::MsgFlags::empty()) {
+			Ok(n) => written += n,
+			Err(_) => break,
+		}
+	}
+	written
+}
+// An unexpected address family, such as of a Unix domain socket, is EAFNOSUPPORT.
+fn inet_addr(addr: socket::SockAddr) -> Result<net::SocketAddr, nix::Error> {
+	match addr {
+		socket::SockAddr::Inet(inet) => Ok(inet.to_std()),
+		_ => Err(nix::Error::Sys(errno::Errno::EAFNOSUPPORT)),
+	}
+}
+// The addresses the OS reports for the socket, for Connection::peer_addr and local_addr.
+pub(crate) fn peer_addr(fd: Fd) -> io::Result<net::SocketAddr> {
+	socket::getpeername(fd)
+		.and_then(inet_addr)
+		.map(unmap_remote)
+		.map_err(nix_to_io)
+}
+pub(crate) fn local_addr(fd: Fd) -> io::Result<net::SocketAddr> {
+	socket::getsockname(fd)
+		.and_then(inet_addr)
+		.map(unmap_remote)
+		.map_err(nix_to_io)
+}
+fn fd_flags(fd: Fd) -> Result<fcntl::OFlag, nix::Error> {
+	fcntl::fcntl(fd, fcntl::FcntlArg::F_GETFL).map(fcntl::OFlag::from_bits_truncate)
+}
+fn set_nonblocking(fd: Fd) -> Result<(), nix::Error> {
+	let flags = fd_flags(fd)?;
+	let _ = fcntl::fcntl(
+		fd,
+		fcntl::FcntlArg::F_SETFL(flags | fcntl::OFlag::O_NONBLOCK),
+	)?;
+	Ok(())
+}
+// Set up a connected socket from elsewhere, per Connected::from_std, failing if it has a pending error.
+fn take_over(fd: Fd, config: &ConnectionConfig) -> Result<(), nix::Error> {
+	set_nonblocking(fd)?;
+	match socket::getsockopt(fd, socket::sockopt::SocketError)? {
+		0 => (),
+		err => return Err(nix::Error::Sys(errno::Errno::from_i32(err))),
+	}
+	set_connection_sockopts(fd, config, false)
+}
+// The options common to inbound and outbound connections. A `fresh` socket is known to have the default options, so those left at their default needn't be set, saving a syscall per connection.
+fn set_connection_sockopts(
+	fd: Fd, config: &ConnectionConfig, fresh: bool,
+) -> Result<(), nix::Error> {
+	socket::setsockopt(
+		fd,
+		socket::sockopt::Linger,
+		&libc::linger {
+			l_onoff: 1,
+			l_linger: 10,
+		},
+	)?; // assert that close is quick?? https://www.nybek.com/blog/2015/04/29/so_linger-on-non-blocking-sockets/
+	if config.no_delay || !fresh {
+		socket::setsockopt(fd, socket::sockopt::TcpNoDelay, &config.no_delay)?;
+	}
+	if let Some(keepalive) = config.keepalive {
+		socket::setsockopt(fd, socket::sockopt::KeepAlive, &true)?;
+		let secs: u32 = cmp::max(keepalive.as_secs(), 1)
+			.try_into()
+			.unwrap_or(u32::MAX);
+		#[cfg(any(target_os = "macos", target_os = "ios"))]
+		socket::setsockopt(fd, socket::sockopt::TcpKeepAlive, &secs)?;
+		#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+		socket::setsockopt(fd, socket::sockopt::TcpKeepIdle, &secs)?;
+	}
+	#[cfg(target_os = "linux")]
+	if let Some(window_clamp) = config.window_clamp {
+		let window_clamp: libc::c_int = window_clamp.try_into().unwrap_or(libc::c_int::MAX);
+		sockopt::set(fd, libc::IPPROTO_TCP, libc::TCP_WINDOW_CLAMP, window_clamp)?;
+	}
+	#[cfg(target_os = "linux")]
+	if let Some(pmtu_discovery) = config.pmtu_discovery {
+		// the IPV6_PMTUDISC_* values are the same
+		let mode = match pmtu_discovery {
+			PmtuDiscovery::Dont => libc::IP_PMTUDISC_DONT,
+			PmtuDiscovery::Want => libc::IP_PMTUDISC_WANT,
+			PmtuDiscovery::Do => libc::IP_PMTUDISC_DO,
+			PmtuDiscovery::Probe => libc::IP_PMTUDISC_PROBE,
+		};
+		if is_ipv6(fd)? {
+			sockopt::set(fd, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, mode)?;
+		} else {
+			sockopt::set(fd, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, mode)?;
+		}
+	}
+	Ok(())
+}
+
+// Whether `fd` is an IPv6 socket, which it may be even with an IPv4 remote, if dualstack.
+#[cfg(target_os = "linux")]
+fn is_ipv6(fd: Fd) -> Result<bool, nix::Error> {
+	Ok(socket::getsockname(fd)?.family() == socket::AddressFamily::Inet6)
+}
+
+// The path MTU as discovered by the kernel.
+#[cfg(target_os = "linux")]
+fn path_mtu(fd: Fd) -> Result<u32, nix::Error> {
+	let mtu = if is_ipv6(fd)? {
+		sockopt::get::<libc::c_int>(fd, libc::IPPROTO_IPV6, libc::IPV6_MTU)?
+	} else {
+		sockopt::get::<libc::c_int>(fd, libc::IPPROTO_IP, libc::IP_MTU)?
+	};
+	Ok(mtu.try_into().unwrap())
+}
+#[cfg(not(target_os = "linux"))]
+fn path_mtu(_fd: Fd) -> Result<u32, nix::Error> {
+	Err(nix::Error::Sys(errno::Errno::ENOSYS))
+}
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
@@ -199,134 +596,410 @@ pub enum ConnecterPoll {
 	RemoteClosed(RemoteClosed),
 	Killed,
 }
+/// The result of [`Connecter::poll_in_place`].
+#[derive(Debug)]
+pub enum ConnecterProgress {
+	/// Still connecting; the `Connecter` remains usable.
+	Connecting,
+	Connected(Connected),
+	RemoteClosed(RemoteClosed),
+	Killed,
+}
 pub struct Connecter {
 	state: Option<Fd>,
 	local: net::SocketAddr,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	timer: Timer,
+	attempts: u32,
+	since: time::Instant,
+	bind_backoff: time::Duration,
+	// set once the connection has moved on, after which the Connecter is inert and can be dropped
+	poisoned: bool,
 }
 impl Connecter {
+	/// Connect to `remote` from `local`, which must be of the same address family, else the connection is killed. The unspecified IP, or port 0, leave the source IP, or port, to the OS.
+	///
+	/// A simultaneous open, where `remote` is concurrently connecting back to `local` such that the SYNs cross, connects as usual. Until the remote's `Connecter` exists its SYN is refused, so the attempt is retried as for any refused connection.
 	pub fn new(
 		local: net::SocketAddr, remote: net::SocketAddr, executor: &impl Notifier,
 	) -> ConnecterPoll {
-		trace!("Connecter connect {}", format_remote(remote));
+		Self::new_with(local, remote, &ConnectionConfig::default(), executor)
+	}
+	/// # Panics
+	///
+	/// If `config` is invalid; see [`ConnectionConfig`].
+	pub fn new_with(
+		local: net::SocketAddr, remote: net::SocketAddr, config: &ConnectionConfig,
+		executor: &impl Notifier,
+	) -> ConnecterPoll {
+		config.validate();
+		trace!("Connecter connect {}", format_remote(remote, config));
 		Self {
 			state: None,
 			local,
 			remote,
+			config: config.clone(),
+			timer: Timer::new(),
+			attempts: 0,
+			since: time::Instant::now(),
+			bind_backoff: BIND_BACKOFF_MIN,
+			poisoned: false,
 		}
 		.poll(executor)
 	}
 	pub fn poll(mut self, executor: &impl Notifier) -> ConnecterPoll {
+		match self.poll_in_place(executor) {
+			ConnecterProgress::Connecting => ConnecterPoll::Connecter(self),
+			ConnecterProgress::Connected(x) => ConnecterPoll::Connected(x),
+			ConnecterProgress::RemoteClosed(x) => ConnecterPoll::RemoteClosed(x),
+			ConnecterProgress::Killed => ConnecterPoll::Killed,
+		}
+	}
+	/// Like [`poll`](Connecter::poll), but by reference. Once this returns anything other than [`ConnecterProgress::Connecting`], this `Connecter` is spent: it can be dropped, but not polled, closed or killed.
+	///
+	/// # Panics
+	///
+	/// If closing the socket of a failed attempt fails.
+	pub fn poll_in_place(&mut self, executor: &impl Notifier) -> ConnecterProgress {
+		debug_assert!(!self.poisoned, "Connecter polled after completion");
+		self.timer.cancel(executor);
+		let mut retired = None;
+		let progress = self.poll_connect(&mut retired, executor);
+		if let Some(fd) = retired {
+			executor.remove_fd(fd);
+			unistd::close(fd).unwrap();
+		}
+		progress
+	}
+	// A socket whose connect is to be retried immediately is left in `retired`, still open and registered, so that its registration can be handed to the next socket with `replace_fd`.
+	#[allow(clippy::too_many_lines)]
+	fn poll_connect(
+		&mut self, retired: &mut Option<Fd>, executor: &impl Notifier,
+	) -> ConnecterProgress {
 		let mut count = 0;
 		loop {
 			count += 1;
-			assert!(count < 1_000);
+			if poll_livelock(count, self.remote, &self.config) {
+				if let Some(fd) = self.state.take() {
+					executor.remove_fd(fd);
+					unistd::close(fd).unwrap();
+				}
+				self.poisoned = true;
+				return ConnecterProgress::Killed;
+			}
 			match self.state {
 				None => {
-					let fd = palaver::socket::socket(
-						socket::AddressFamily::Inet,
-						socket::SockType::Stream,
-						palaver::socket::SockFlag::SOCK_CLOEXEC
-							| palaver::socket::SockFlag::SOCK_NONBLOCK,
-						socket::SockProtocol::Tcp,
-					)
-					.unwrap();
-					socket::setsockopt(fd, socket::sockopt::ReusePort, &true).unwrap();
-					socket::setsockopt(fd, socket::sockopt::ReuseAddr, &true).unwrap();
-					socket::setsockopt(
-						fd,
-						socket::sockopt::Linger,
-						&libc::linger {
-							l_onoff: 1,
-							l_linger: 10,
-						},
-					)
-					.unwrap();
-					socket::setsockopt(fd, socket::sockopt::TcpNoDelay, &true).unwrap();
-					socket::bind(
-						fd,
-						&socket::SockAddr::Inet(socket::InetAddr::from_std(&self.local)),
-					)
-					.unwrap();
-					executor.add_fd(fd);
-					trace!("Connecter connecting {}", format_remote(self.remote));
-					if match socket::connect(
+					let fd = match self.socket() {
+						Ok(fd) => fd,
+						Err(err) => return self.fail(err),
+					};
+					if let Some(range) = self.config.local_port_range.clone() {
+						let bound = range
+							.into_iter()
+							.map(|port| {
+								let local = net::SocketAddr::new(self.local.ip(), port);
+								socket::bind(
+									fd,
+									&socket::SockAddr::Inet(socket::InetAddr::from_std(&local)),
+								)
+							})
+							.find(|bound| {
+								!matches!(
+									bound,
+									Err(nix::Error::Sys(
+										errno::Errno::EADDRINUSE | errno::Errno::EADDRNOTAVAIL,
+									))
+								)
+							});
+						match bound {
+							Some(Ok(())) => (),
+							Some(Err(err)) => {
+								unistd::close(fd).unwrap();
+								return self.fail(err);
+							}
+							None => {
+								trace!(
+									"Connecter no free local port {}",
+									format_remote(self.remote, &self.config)
+								);
+								unistd::close(fd).unwrap();
+								self.poisoned = true;
+								return ConnecterProgress::Killed;
+							}
+						}
+					} else {
+						match socket::bind(
+							fd,
+							&socket::SockAddr::Inet(socket::InetAddr::from_std(&self.local)),
+						) {
+							Ok(()) => self.bind_backoff = BIND_BACKOFF_MIN,
+							// the address is held in a way the reuse options don't cover, or its IP isn't (yet) assigned; back off until it's released rather than retrying every millisecond
+							Err(
+								err @ nix::Error::Sys(
+									errno::Errno::EADDRINUSE | errno::Errno::EADDRNOTAVAIL,
+								),
+							) => {
+								unistd::close(fd).unwrap();
+								self.retry(err);
+								let timeout = time::Instant::now() + self.bind_backoff;
+								self.bind_backoff =
+									cmp::min(self.bind_backoff * 2, BIND_BACKOFF_MAX);
+								trace!(
+									"Connecter rebind {} {:?}",
+									format_remote(self.remote, &self.config),
+									timeout
+								);
+								self.timer.set(timeout, executor);
+								return ConnecterProgress::Connecting;
+							}
+							Err(err) => {
+								unistd::close(fd).unwrap();
+								return self.fail(err);
+							}
+						}
+					}
+					if let Some(old) = retired.take() {
+						executor.replace_fd(old, fd);
+						unistd::close(old).unwrap();
+					} else {
+						executor.add_fd(fd);
+					}
+					self.attempts += 1;
+					trace!(
+						"Connecter connecting {}",
+						format_remote(self.remote, &self.config)
+					);
+					let connect = socket::connect(
 						fd,
 						&socket::SockAddr::Inet(socket::InetAddr::from_std(&self.remote)),
-					) {
-						Err(nix::Error::Sys(errno::Errno::EINPROGRESS)) => true,
-						Err(nix::Error::Sys(errno::Errno::EADDRNOTAVAIL)) => false,
-						Err(nix::Error::Sys(errno::Errno::ECONNABORTED)) => {
+					);
+					let so_error = match connect {
+						Err(nix::Error::Sys(errno::Errno::EINPROGRESS)) => {
+							socket::getsockopt(fd, socket::sockopt::SocketError).unwrap()
+						}
+						_ => 0,
+					};
+					match connect {
+						Err(nix::Error::Sys(errno::Errno::EINPROGRESS)) if so_error == 0 => {
+							trace!(
+								"Connecter connect in progress {}",
+								format_remote(self.remote, &self.config)
+							);
+							self.state = Some(fd);
+						}
+						Err(nix::Error::Sys(errno::Errno::ECONNABORTED))
+							if count < CONNECT_IMMEDIATE_RETRIES =>
+						{
+							// transient, so retry immediately rather than waiting
 							trace!("Connecter ECONNABORTED");
-							false
+							self.retry(nix::Error::Sys(errno::Errno::ECONNABORTED));
+							*retired = Some(fd);
 						}
-						err => panic!("Connecter err {:?}", err),
-					} && socket::getsockopt(fd, socket::sockopt::SocketError).unwrap() == 0
-					{
-						// sometimes ECONNRESET; sometimes ECONNREFUSED (after remote segfaulted?)
-						trace!(
-							"Connecter connect in progress {}",
-							format_remote(self.remote)
-						);
-						self.state = Some(fd);
-					} else {
-						executor.remove_fd(fd);
-						unistd::close(fd).unwrap();
-						let timeout = time::Instant::now() + time::Duration::new(0, 1_000_000);
-						trace!(
-							"Connecter reconnect {} {:?}",
-							format_remote(self.remote),
-							timeout
-						);
-						let _ = executor.add_instant(timeout);
-						return ConnecterPoll::Connecter(self);
+						// EINPROGRESS with sometimes ECONNRESET; sometimes ECONNREFUSED (after remote segfaulted?)
+						Err(
+							err @ nix::Error::Sys(
+								errno::Errno::EINPROGRESS
+								| errno::Errno::EADDRNOTAVAIL
+								| errno::Errno::ECONNABORTED,
+							),
+						) => {
+							executor.remove_fd(fd);
+							unistd::close(fd).unwrap();
+							self.retry(if so_error != 0 {
+								nix::Error::Sys(errno::Errno::from_i32(so_error))
+							} else {
+								err
+							});
+							let timeout = time::Instant::now() + time::Duration::new(0, 1_000_000);
+							trace!(
+								"Connecter reconnect {} {:?}",
+								format_remote(self.remote, &self.config),
+								timeout
+							);
+							self.timer.set(timeout, executor);
+							return ConnecterProgress::Connecting;
+						}
+						// such as ENETUNREACH, or EACCES from a firewall
+						Err(err) => {
+							executor.remove_fd(fd);
+							unistd::close(fd).unwrap();
+							return self.fail(err);
+						}
+						// some platforms connect to loopback synchronously
+						Ok(()) => self.state = Some(fd),
 					}
 				}
 				Some(fd) => {
 					let x = socket::getsockopt(fd, socket::sockopt::SocketError).unwrap();
 					if x == 0 {
+						// by writability, as getpeername already succeeds in SYN_RECV, partway through a simultaneous open
 						if palaver::socket::is_connected(fd) {
-							trace!("Connecter connected {}", format_remote(self.remote));
-							let ret = match Connected::new(fd, executor, self.remote) {
-								ConnectedPoll::Connected(x) => ConnecterPoll::Connected(x),
-								ConnectedPoll::RemoteClosed(x) => ConnecterPoll::RemoteClosed(x),
-								ConnectedPoll::Killed => ConnecterPoll::Killed,
+							trace!(
+								"Connecter connected {}",
+								format_remote(self.remote, &self.config)
+							);
+							self.state = None;
+							self.poisoned = true;
+							return match Connected::new(
+								fd,
+								mem::take(&mut self.config),
+								executor,
+								self.remote,
+								Origin::Dialer,
+							) {
+								ConnectedPoll::Connected(x) => ConnecterProgress::Connected(x),
+								ConnectedPoll::RemoteClosed(x) => {
+									ConnecterProgress::RemoteClosed(x)
+								}
+								ConnectedPoll::Reset(x) => {
+									x.kill(executor);
+									ConnecterProgress::Killed
+								}
+								ConnectedPoll::Killed => ConnecterProgress::Killed,
 							};
-							mem::forget(self);
-							return ret;
 						} else {
 							assert_ne!(self.state, None);
-							return ConnecterPoll::Connecter(self);
+							return ConnecterProgress::Connecting;
 						}
 					} else {
 						trace!(
 							"Connecter err {} {:?}",
-							format_remote(self.remote),
+							format_remote(self.remote, &self.config),
 							errno::Errno::from_i32(x)
 						);
-						executor.remove_fd(fd);
-						unistd::close(fd).unwrap();
+						self.retry(nix::Error::Sys(errno::Errno::from_i32(x)));
+						*retired = Some(fd);
 						self.state = None;
 					}
 				}
 			}
 		}
 	}
-	pub fn close(self, executor: &impl Notifier) -> ConnecterLocalClosedPoll {
-		let ret = ConnecterLocalClosed::new(self.state, self.local, self.remote, executor);
+	fn socket(&self) -> Result<Fd, nix::Error> {
+		let fd = palaver::socket::socket(
+			address_family(&self.remote),
+			socket::SockType::Stream,
+			palaver::socket::SockFlag::SOCK_CLOEXEC | palaver::socket::SockFlag::SOCK_NONBLOCK,
+			socket::SockProtocol::Tcp,
+		)?;
+		(|| {
+			// SO_REUSEADDR permits binding a port held by a connection in TIME_WAIT, and SO_REUSEPORT one held by other sockets that also set it, leaving collisions to surface as EADDRNOTAVAIL from connect. With a port range, ports in use instead need to be detected by bind, so that the next can be tried.
+			if self.config.local_port_range.is_none() {
+				socket::setsockopt(fd, socket::sockopt::ReusePort, &true)?;
+				socket::setsockopt(fd, socket::sockopt::ReuseAddr, &true)?;
+			}
+			set_connection_sockopts(fd, &self.config, true)
+		})()
+		.map(|()| fd)
+		.inspect_err(|_| unistd::close(fd).unwrap())
+	}
+	// A reconnect is being scheduled due to `err`.
+	fn retry(&self, err: nix::Error) {
+		if let Some(on_retry) = &self.config.on_retry {
+			on_retry.call(self.attempts, &nix_to_io(err));
+		}
+	}
+	// Socket creation or binding failed for a reason other than the port being in use, such as the process being out of fds.
+	fn fail(&mut self, err: nix::Error) -> ConnecterProgress {
+		trace!(
+			"Connecter err {} {:?}",
+			format_remote(self.remote, &self.config),
+			err
+		);
+		self.poisoned = true;
+		ConnecterProgress::Killed
+	}
+	/// The number of times `connect` has been attempted, including immediate retries.
+	#[must_use]
+	#[inline(always)]
+	pub fn attempts(&self) -> u32 {
+		self.attempts
+	}
+	/// When this `Connecter` was created.
+	#[must_use]
+	#[inline(always)]
+	pub fn connecting_since(&self) -> time::Instant {
+		self.since
+	}
+	/// Like [`close`](Connecter::close), but with the given [`ConnectionConfig::close_timeout`], after which the connection is reset.
+	pub fn close_with_timeout(
+		mut self, timeout: time::Duration, executor: &impl Notifier,
+	) -> ConnecterLocalClosedPoll {
+		self.config.close_timeout = Some(timeout);
+		self.close(executor)
+	}
+	pub fn close(mut self, executor: &impl Notifier) -> ConnecterLocalClosedPoll {
+		debug_assert!(!self.poisoned, "Connecter closed after completion");
+		self.timer.cancel(executor);
+		let ret = ConnecterLocalClosed::new(
+			self.state,
+			self.local,
+			self.remote,
+			mem::take(&mut self.config),
+			executor,
+		);
 		mem::forget(self);
 		ret
 	}
-	pub fn kill(self, executor: &impl Notifier) {
-		if let Some(fd) = self.state {
+	/// Like [`kill`](Connecter::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "Connecter killed after completion");
+		self.timer.cancel(executor);
+		if let Some(fd) = self.state.take() {
 			executor.remove_fd(fd);
 			unistd::close(fd).unwrap();
 		}
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		// connection completion is signalled by writability
+		Interest {
+			read: false,
+			write: self.state.is_some(),
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket, if there is one.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Option<Fd> {
+		self.state
+	}
+}
+impl StateInfo for Connecter {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		Origin::Dialer
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for Connecter {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop Connecter");
 	}
 }
@@ -337,6 +1010,12 @@ impl fmt::Debug for Connecter {
 			.field("socket", &self.state.map(socketstat::socketstat))
 			.field("local", &self.local)
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("timer", &self.timer)
+			.field("attempts", &self.attempts)
+			.field("since", &self.since)
+			.field("bind_backoff", &self.bind_backoff)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
@@ -353,19 +1032,103 @@ pub enum ConnecteePoll {
 pub struct Connectee {
 	fd: Fd,
 	remote: net::SocketAddr,
+	// awaiting a PROXY protocol header
+	proxy_protocol: bool,
+	config: ConnectionConfig,
+	since: time::Instant,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl Connectee {
-	fn new(fd: Fd, executor: &impl Notifier, remote: net::SocketAddr) -> ConnecteePoll {
-		Self { fd, remote }.poll(executor)
+	fn new(
+		fd: Fd, config: ConnectionConfig, proxy_protocol: bool, executor: &impl Notifier,
+		remote: net::SocketAddr,
+	) -> ConnecteePoll {
+		Self {
+			fd,
+			remote,
+			proxy_protocol,
+			config,
+			since: time::Instant::now(),
+			poisoned: false,
+		}
+		.poll(executor)
 	}
-	pub fn poll(self, executor: &impl Notifier) -> ConnecteePoll {
+	pub fn poll(mut self, executor: &impl Notifier) -> ConnecteePoll {
+		debug_assert!(!self.poisoned, "Connectee polled after kill_in_place");
 		let x = socket::getsockopt(self.fd, socket::sockopt::SocketError).unwrap();
 		if x == 0 {
 			if palaver::socket::is_connected(self.fd) {
-				trace!("Connectee accepted {}", format_remote(self.remote));
-				let ret = match Connected::new(self.fd, executor, self.remote) {
+				if self.proxy_protocol {
+					// peek until the whole header has arrived, as it may be split across segments, so as to consume only it
+					let mut buf = vec![0; proxy_protocol::PEEK_LEN];
+					loop {
+						let header =
+							match socket::recv(self.fd, &mut buf, socket::MsgFlags::MSG_PEEK) {
+								Ok(len) if len > 0 => proxy_protocol::parse(&buf[..len]),
+								Err(nix::Error::Sys(errno::Errno::EAGAIN)) => {
+									return ConnecteePoll::Connectee(self)
+								}
+								_ => proxy_protocol::Header::Invalid,
+							};
+						match header {
+							proxy_protocol::Header::Incomplete(len) if len > buf.len() => {
+								buf.resize(len, 0);
+							}
+							proxy_protocol::Header::Incomplete(_) => {
+								return ConnecteePoll::Connectee(self)
+							}
+							proxy_protocol::Header::Complete(len, remote) => {
+								// peeked, so should all be there, but the socket may have errored since
+								let read = socket::recv(
+									self.fd,
+									&mut buf[..len],
+									socket::MsgFlags::empty(),
+								);
+								if read != Ok(len) {
+									trace!(
+										"Connectee failed consuming PROXY header {} {:?}",
+										format_remote(self.remote, &self.config),
+										read
+									);
+									set_reset_on_close(self.fd);
+									self.kill(executor);
+									return ConnecteePoll::Killed;
+								}
+								if let Some(remote) = remote {
+									self.remote = unmap_remote(remote);
+								}
+								self.proxy_protocol = false;
+								break;
+							}
+							proxy_protocol::Header::Invalid => {
+								trace!(
+									"Connectee invalid PROXY header {}",
+									format_remote(self.remote, &self.config)
+								);
+								self.kill(executor);
+								return ConnecteePoll::Killed;
+							}
+						}
+					}
+				}
+				trace!(
+					"Connectee accepted {}",
+					format_remote(self.remote, &self.config)
+				);
+				let ret = match Connected::new(
+					self.fd,
+					mem::take(&mut self.config),
+					executor,
+					self.remote,
+					Origin::Listener,
+				) {
 					ConnectedPoll::Connected(x) => ConnecteePoll::Connected(x),
 					ConnectedPoll::RemoteClosed(x) => ConnecteePoll::RemoteClosed(x),
+					ConnectedPoll::Reset(x) => {
+						x.kill(executor);
+						ConnecteePoll::Killed
+					}
 					ConnectedPoll::Killed => ConnecteePoll::Killed,
 				};
 				mem::forget(self);
@@ -376,25 +1139,81 @@ impl Connectee {
 		} else {
 			trace!(
 				"Connectee err {} {:?}",
-				format_remote(self.remote),
+				format_remote(self.remote, &self.config),
 				errno::Errno::from_i32(x),
 			);
+			self.kill(executor);
 			ConnecteePoll::Killed
 		}
 	}
-	pub fn close(self, executor: &impl Notifier) -> ConnecteeLocalClosedPoll {
-		let ret = ConnecteeLocalClosed::new(self.fd, executor, self.remote);
+	/// Like [`close`](Connectee::close), but with the given [`ConnectionConfig::close_timeout`], after which the connection is reset.
+	pub fn close_with_timeout(
+		mut self, timeout: time::Duration, executor: &impl Notifier,
+	) -> ConnecteeLocalClosedPoll {
+		self.config.close_timeout = Some(timeout);
+		self.close(executor)
+	}
+	pub fn close(mut self, executor: &impl Notifier) -> ConnecteeLocalClosedPoll {
+		debug_assert!(!self.poisoned, "Connectee closed after kill_in_place");
+		let ret =
+			ConnecteeLocalClosed::new(self.fd, mem::take(&mut self.config), executor, self.remote);
 		mem::forget(self);
 		ret
 	}
-	pub fn kill(self, executor: &impl Notifier) {
+	/// Like [`kill`](Connectee::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "Connectee killed twice");
 		executor.remove_fd(self.fd);
 		unistd::close(self.fd).unwrap();
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		Interest {
+			read: self.proxy_protocol,
+			write: !self.proxy_protocol,
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		self.fd
+	}
+}
+impl StateInfo for Connectee {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		Origin::Listener
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for Connectee {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop Connectee");
 	}
 }
@@ -404,6 +1223,10 @@ impl fmt::Debug for Connectee {
 			.field("fd", &self.fd)
 			.field("socket", &socketstat::socketstat(self.fd))
 			.field("remote", &self.remote)
+			.field("proxy_protocol", &self.proxy_protocol)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
@@ -422,26 +1245,41 @@ pub struct ConnecterLocalClosed {
 	state: Option<Fd>,
 	local: net::SocketAddr,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl ConnecterLocalClosed {
 	fn new(
 		state: Option<Fd>, local: net::SocketAddr, remote: net::SocketAddr,
-		executor: &impl Notifier,
+		config: ConnectionConfig, executor: &impl Notifier,
 	) -> ConnecterLocalClosedPoll {
 		Self {
 			state,
 			local,
 			remote,
+			config,
+			since: time::Instant::now(),
+			poisoned: false,
 		}
 		.poll(executor)
 	}
 	pub fn poll(mut self, executor: &impl Notifier) -> ConnecterLocalClosedPoll {
+		debug_assert!(
+			!self.poisoned,
+			"ConnecterLocalClosed polled after kill_in_place"
+		);
 		let mut count = 0;
 		loop {
 			count += 1;
-			assert!(count < 1_000);
+			if poll_livelock(count, self.remote, &self.config) {
+				self.kill(executor);
+				return ConnecterLocalClosedPoll::Killed;
+			}
 			match self.state {
 				None => {
+					let _ = mem::take(&mut self.config);
 					mem::forget(self);
 					return ConnecterLocalClosedPoll::Closed;
 				}
@@ -451,21 +1289,30 @@ impl ConnecterLocalClosed {
 						if palaver::socket::is_connected(fd) {
 							trace!(
 								"ConnecterLocalClosed connected {}",
-								format_remote(self.remote)
+								format_remote(self.remote, &self.config)
 							);
 							let ret = match LocalClosed::new(
 								fd,
-								CircularBuffer::new(BUF),
-								CircularBuffer::new(BUF),
+								CircularBuffer::new(self.config.send_buf),
+								CircularBuffer::new(self.config.recv_buf),
+								false,
 								false,
+								None,
+								close_deadline(&self.config, executor),
+								mem::take(&mut self.config),
 								executor,
 								self.remote,
+								Origin::Dialer,
 							) {
 								LocalClosedPoll::LocalClosed(x) => {
 									ConnecterLocalClosedPoll::LocalClosed(x)
 								}
 								LocalClosedPoll::Closing(x) => ConnecterLocalClosedPoll::Closing(x),
 								LocalClosedPoll::Closed => ConnecterLocalClosedPoll::Closed,
+								LocalClosedPoll::Reset(x) => {
+									x.kill(executor);
+									ConnecterLocalClosedPoll::Killed
+								}
 								LocalClosedPoll::Killed => ConnecterLocalClosedPoll::Killed,
 							};
 							mem::forget(self);
@@ -477,7 +1324,7 @@ impl ConnecterLocalClosed {
 					} else {
 						trace!(
 							"ConnecterLocalClosed err {} {:?}",
-							format_remote(self.remote),
+							format_remote(self.remote, &self.config),
 							errno::Errno::from_i32(x)
 						);
 						executor.remove_fd(fd);
@@ -488,16 +1335,62 @@ impl ConnecterLocalClosed {
 			}
 		}
 	}
-	pub fn kill(self, executor: &impl Notifier) {
+	/// Like [`kill`](ConnecterLocalClosed::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "ConnecterLocalClosed killed twice");
 		if let Some(fd) = self.state {
 			executor.remove_fd(fd);
 			unistd::close(fd).unwrap();
 		}
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		Interest {
+			read: false,
+			write: self.state.is_some(),
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket, if there is one.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Option<Fd> {
+		self.state
+	}
+}
+impl StateInfo for ConnecterLocalClosed {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		Origin::Dialer
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for ConnecterLocalClosed {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop ConnecterLocalClosed");
 	}
 }
@@ -508,6 +1401,9 @@ impl fmt::Debug for ConnecterLocalClosed {
 			.field("socket", &self.state.map(socketstat::socketstat))
 			.field("local", &self.local)
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
@@ -525,30 +1421,56 @@ pub enum ConnecteeLocalClosedPoll {
 pub struct ConnecteeLocalClosed {
 	fd: Fd,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl ConnecteeLocalClosed {
-	fn new(fd: Fd, executor: &impl Notifier, remote: net::SocketAddr) -> ConnecteeLocalClosedPoll {
-		Self { fd, remote }.poll(executor)
+	fn new(
+		fd: Fd, config: ConnectionConfig, executor: &impl Notifier, remote: net::SocketAddr,
+	) -> ConnecteeLocalClosedPoll {
+		Self {
+			fd,
+			remote,
+			config,
+			since: time::Instant::now(),
+			poisoned: false,
+		}
+		.poll(executor)
 	}
-	pub fn poll(self, executor: &impl Notifier) -> ConnecteeLocalClosedPoll {
+	pub fn poll(mut self, executor: &impl Notifier) -> ConnecteeLocalClosedPoll {
+		debug_assert!(
+			!self.poisoned,
+			"ConnecteeLocalClosed polled after kill_in_place"
+		);
 		let x = socket::getsockopt(self.fd, socket::sockopt::SocketError).unwrap();
 		if x == 0 {
 			if palaver::socket::is_connected(self.fd) {
 				trace!(
 					"ConnecteeLocalClosed accepted {}",
-					format_remote(self.remote)
+					format_remote(self.remote, &self.config)
 				);
 				let ret = match LocalClosed::new(
 					self.fd,
-					CircularBuffer::new(BUF),
-					CircularBuffer::new(BUF),
+					CircularBuffer::new(self.config.send_buf),
+					CircularBuffer::new(self.config.recv_buf),
+					false,
 					false,
+					None,
+					close_deadline(&self.config, executor),
+					mem::take(&mut self.config),
 					executor,
 					self.remote,
+					Origin::Listener,
 				) {
 					LocalClosedPoll::LocalClosed(x) => ConnecteeLocalClosedPoll::LocalClosed(x),
 					LocalClosedPoll::Closing(x) => ConnecteeLocalClosedPoll::Closing(x),
 					LocalClosedPoll::Closed => ConnecteeLocalClosedPoll::Closed,
+					LocalClosedPoll::Reset(x) => {
+						x.kill(executor);
+						ConnecteeLocalClosedPoll::Killed
+					}
 					LocalClosedPoll::Killed => ConnecteeLocalClosedPoll::Killed,
 				};
 				mem::forget(self);
@@ -559,21 +1481,68 @@ impl ConnecteeLocalClosed {
 		} else {
 			trace!(
 				"ConnecteeLocalClosed err {} {:?}",
-				format_remote(self.remote),
+				format_remote(self.remote, &self.config),
 				errno::Errno::from_i32(x),
 			);
+			self.kill(executor);
 			ConnecteeLocalClosedPoll::Killed
 		}
 	}
-	pub fn kill(self, executor: &impl Notifier) {
+	/// Like [`kill`](ConnecteeLocalClosed::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "ConnecteeLocalClosed killed twice");
 		executor.remove_fd(self.fd);
 		unistd::close(self.fd).unwrap();
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
 	}
-}
-impl Drop for ConnecteeLocalClosed {
-	fn drop(&mut self) {
-		panic!("Don't drop ConnecteeLocalClosed");
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		Interest {
+			read: false,
+			write: true,
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		self.fd
+	}
+}
+impl StateInfo for ConnecteeLocalClosed {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		Origin::Listener
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
+	}
+}
+impl Drop for ConnecteeLocalClosed {
+	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
+		panic!("Don't drop ConnecteeLocalClosed");
 	}
 }
 impl fmt::Debug for ConnecteeLocalClosed {
@@ -582,6 +1551,9 @@ impl fmt::Debug for ConnecteeLocalClosed {
 			.field("fd", &self.fd)
 			.field("socket", &socketstat::socketstat(self.fd))
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
@@ -592,59 +1564,242 @@ impl fmt::Debug for ConnecteeLocalClosed {
 pub enum ConnectedPoll {
 	Connected(Connected),
 	RemoteClosed(RemoteClosed),
+	Reset(Reset),
 	Killed,
 }
+/// How far [`Connected::flush_and_wait`] has got.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FlushProgress {
+	/// Bytes remain in the send buffer, or have been written to the kernel but not yet acknowledged.
+	Flushing { buffered: usize, unacked: usize },
+	/// Everything sent has been acknowledged by the remote.
+	Flushed,
+}
+/// What the process handing over a connection to [`Connected::adopt_fd`] had seen of it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AdoptState {
+	/// It was [`Connected`].
+	Connected,
+	/// It was [`RemoteClosed`]: the remote had closed, and everything it sent had been read.
+	RemoteClosed,
+}
+/// The health of a connection as found by [`Connected::probe`].
+#[derive(Debug)]
+pub enum ProbeResult {
+	/// Nothing suggests the connection is other than healthy.
+	Alive,
+	/// The remote has closed its side, though it may still be receiving what's sent to it.
+	RemoteClosed,
+	/// The connection has failed, with this error. It should be killed.
+	Dead(io::Error),
+}
+#[allow(clippy::struct_excessive_bools)]
 pub struct Connected {
 	fd: Fd,
 	send: Option<CircularBuffer<u8>>,
 	recv: Option<CircularBuffer<u8>>,
 	remote_closed: bool,
+	recv_timestamps: bool,
+	last_recv_timestamp: Option<time::Duration>,
+	drain_recv: bool,
+	send_deadline: Timer,
+	idle: Option<IdleTimeout>,
+	idle_timer: Timer,
+	// consecutive polls that hit ENOBUFS or ENOMEM, and when to retry after the latest
+	transient_errors: u32,
+	transient_retry: Timer,
+	// re-polls flush_and_wait while the kernel has unacknowledged data, after the delay, which grows while it's waiting
+	flush_timer: Timer,
+	flush_backoff: Option<time::Duration>,
+	// an error consumed from the socket by probe, for the next poll to act on
+	probe_err: Option<nix::Error>,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	origin: Origin,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl Connected {
-	fn new(fd: Fd, executor: &impl Notifier, remote: net::SocketAddr) -> ConnectedPoll {
+	fn new(
+		fd: Fd, config: ConnectionConfig, executor: &impl Notifier, remote: net::SocketAddr,
+		origin: Origin,
+	) -> ConnectedPoll {
 		Self {
 			fd,
-			send: Some(CircularBuffer::new(BUF)),
-			recv: Some(CircularBuffer::new(BUF)),
+			send: Some(CircularBuffer::new(config.send_buf)),
+			recv: Some(CircularBuffer::new(config.recv_buf)),
 			remote_closed: false,
+			recv_timestamps: false,
+			last_recv_timestamp: None,
+			drain_recv: false,
+			send_deadline: Timer::new(),
+			idle: None,
+			idle_timer: Timer::new(),
+			transient_errors: 0,
+			transient_retry: Timer::new(),
+			flush_timer: Timer::new(),
+			flush_backoff: None,
+			probe_err: None,
 			remote,
+			config,
+			since: time::Instant::now(),
+			origin,
+			poisoned: false,
 		}
 		.poll(executor)
 	}
+	#[allow(clippy::too_many_lines)]
 	pub fn poll(mut self, executor: &impl Notifier) -> ConnectedPoll {
-		match self.send.as_mut().unwrap().read_to_fd(self.fd) {
-			Ok(_written) => (),
+		debug_assert!(!self.poisoned, "Connected polled after kill_in_place");
+		if let Some(err) = self.probe_err.take() {
+			trace!(
+				"Connected probed err {} {:?}",
+				format_remote(self.remote, &self.config),
+				err,
+			);
+			return self.reset(err, executor);
+		}
+		let send_full = self.send_avail() == 0;
+		let written = match self.send.as_mut().unwrap().read_to_fd(self.fd) {
+			Ok(written) => {
+				if send_full && written > 0 {
+					// rewake any writer blocked on the full send buffer
+					executor.queue();
+				}
+				written
+			}
 			Err(err) => {
-				trace!("Connected err {} {:?}", format_remote(self.remote), err,);
+				if self.retry_transient(err, executor) {
+					return ConnectedPoll::Connected(self);
+				}
+				trace!(
+					"Connected err {} {:?}",
+					format_remote(self.remote, &self.config),
+					err,
+				);
+				return self.reset(err, executor);
+			}
+		};
+		let mut read_any = false;
+		if let Some(deadline) = self.send_deadline.instant() {
+			if self.send.as_ref().unwrap().read_available() == 0
+				&& palaver::socket::unsent(self.fd) == 0
+			{
+				self.send_deadline.cancel(executor);
+			} else if time::Instant::now() >= deadline {
+				trace!(
+					"Connected send deadline passed {}",
+					format_remote(self.remote, &self.config)
+				);
+				// the unacknowledged data is abandoned, so tell the remote rather than having the kernel keep retransmitting it
+				set_reset_on_close(self.fd);
 				self.kill(executor);
 				return ConnectedPoll::Killed;
 			}
 		}
 		if !self.remote_closed {
-			match self.recv.as_mut().unwrap().write_from_fd(self.fd) {
-				Ok((_read, false)) => (),
-				Ok((_read, true)) => {
-					trace!("Connected got closed {}", format_remote(self.remote));
-					#[cfg(any(target_os = "macos", target_os = "ios"))]
-					assert_ne!(sockstate::sockstate(self.fd), sockstate::TcpState::ESTABLISHED, "this is a bug in macOS; see tcp_typed/src/socket_forwarder.rs for a mitigation");
-					self.remote_closed = true;
+			loop {
+				if self.drain_recv {
+					self.recv.as_mut().unwrap().discard();
 				}
-				Err(err) => {
-					trace!("Connected err {} {:?}", format_remote(self.remote), err,);
-					self.kill(executor);
-					return ConnectedPoll::Killed;
+				let timestamp = if self.recv_timestamps {
+					Some(&mut self.last_recv_timestamp)
+				} else {
+					None
+				};
+				match self.recv.as_mut().unwrap().write_from_fd(
+					self.fd,
+					timestamp,
+					self.config.recv_full.max_capacity(),
+				) {
+					Ok((read, false)) => {
+						read_any |= read > 0;
+						if self.drain_recv && read > 0 {
+							continue;
+						}
+					}
+					Ok((_read, true)) => {
+						trace!(
+							"Connected got closed {}",
+							format_remote(self.remote, &self.config)
+						);
+						#[cfg(any(target_os = "macos", target_os = "ios"))]
+						{
+							let spurious = sockstate::eof_while_established(self.fd);
+							debug_assert!(!spurious, "this is a bug in macOS; see tcp_typed/src/socket_forwarder.rs for a mitigation");
+							if spurious {
+								// the stream has nonetheless ended, so treat it as closed rather than stalling
+								trace!(
+									"Connected closed while ESTABLISHED {}",
+									format_remote(self.remote, &self.config)
+								);
+							}
+						}
+						self.remote_closed = true;
+					}
+					Err(err) => {
+						if self.retry_transient(err, executor) {
+							return ConnectedPoll::Connected(self);
+						}
+						trace!(
+							"Connected err {} {:?}",
+							format_remote(self.remote, &self.config),
+							err,
+						);
+						return self.reset(err, executor);
+					}
 				}
+				break;
+			}
+			if self.drain_recv {
+				self.recv.as_mut().unwrap().discard();
+			}
+			if read_any && self.config.quick_ack {
+				set_quick_ack(self.fd);
+			}
+			if recv_overflowed(self.fd, self.recv.as_ref().unwrap(), self.config.recv_full) {
+				trace!(
+					"Connected recv overflow {}",
+					format_remote(self.remote, &self.config)
+				);
+				set_reset_on_close(self.fd);
+				self.kill(executor);
+				return ConnectedPoll::Killed;
+			}
+		}
+		if self.transient_errors != 0 {
+			self.transient_errors = 0;
+			self.transient_retry.cancel(executor);
+		}
+		if let Some(deadline) = self.idle_deadline(written > 0, read_any) {
+			if time::Instant::now() >= deadline {
+				trace!(
+					"Connected idle timeout {}",
+					format_remote(self.remote, &self.config)
+				);
+				set_reset_on_close(self.fd);
+				self.kill(executor);
+				return ConnectedPoll::Killed;
+			}
+			if self.idle_timer.instant() != Some(deadline) {
+				self.idle_timer.set(deadline, executor);
 			}
 		}
 		if !self.remote_closed || self.recv.as_mut().unwrap().read_available() > 0 {
 			ConnectedPoll::Connected(self)
 		} else {
+			self.send_deadline.cancel(executor);
+			self.idle_timer.cancel(executor);
+			self.transient_retry.cancel(executor);
+			self.flush_timer.cancel(executor);
 			let ret = match RemoteClosed::new(
 				self.fd,
 				self.send.take().unwrap(),
+				mem::take(&mut self.config),
 				executor,
 				self.remote,
+				self.origin,
 			) {
 				RemoteClosedPoll::RemoteClosed(x) => ConnectedPoll::RemoteClosed(x),
 				RemoteClosedPoll::Killed => ConnectedPoll::Killed,
@@ -654,10 +1809,93 @@ impl Connected {
 			ret
 		}
 	}
+	/// Take over the connected `stream`, making it non-blocking and setting the same socket options as for connections made or accepted by this crate. `origin` is whether it was dialed or accepted, as that can't be told from the socket. Returns `ENOTCONN` if it isn't connected.
+	///
+	/// # Errors
+	///
+	/// If the socket can't be set up, such as `ENOTCONN` if it isn't connected, in which case it's closed.
+	///
+	/// # Panics
+	///
+	/// If closing the socket after an error fails.
+	pub fn from_std(
+		stream: net::TcpStream, origin: Origin, executor: &impl Notifier,
+	) -> io::Result<ConnectedPoll> {
+		let config = ConnectionConfig::default();
+		let fd = stream.into_raw_fd();
+		let remote = take_over(fd, &config)
+			.and_then(|()| Ok(unmap_remote(inet_addr(socket::getpeername(fd)?)?)))
+			.inspect_err(|err| {
+				trace!("Connected from_std err {err:?}");
+				unistd::close(fd).unwrap();
+			})
+			.map_err(nix_to_io)?;
+		trace!("Connected from_std {}", format_remote(remote, &config));
+		executor.add_fd(fd);
+		executor.queue();
+		Ok(Self::new(fd, config, executor, remote, origin))
+	}
+	/// Take over `fd`, a connection handed over by another process, such as with [`into_raw_fd`](Connected::into_raw_fd) and a [`SocketForwarder`] for a zero-downtime upgrade. `remote`, `origin` and `assumed_state` are as that process had them. The socket is set up as with [`from_std`](Connected::from_std).
+	///
+	/// The buffers start empty: what's in the kernel carries over, but anything the other process had yet to write out of, or consume from, its own buffers is for it to pass on separately. This resumes as [`RemoteClosed`] if `assumed_state` says so or, on Linux and Android, if the remote's FIN has arrived (`TCP_CLOSE_WAIT`), in either case only if nothing is waiting to be read; otherwise as `Connected`, which reads what remains. Returns the socket's pending error, if any, having closed it.
+	///
+	/// # Errors
+	///
+	/// If the socket can't be set up, such as if it has a pending error, in which case it's closed.
+	///
+	/// # Panics
+	///
+	/// If closing the socket after an error fails.
+	pub fn adopt_fd(
+		fd: Fd, remote: net::SocketAddr, origin: Origin, assumed_state: AdoptState,
+		executor: &impl Notifier,
+	) -> io::Result<ConnectedPoll> {
+		let config = ConnectionConfig::default();
+		take_over(fd, &config)
+			.inspect_err(|err| {
+				trace!("Connected adopt_fd err {err:?}");
+				unistd::close(fd).unwrap();
+			})
+			.map_err(nix_to_io)?;
+		#[cfg(any(target_os = "android", target_os = "linux"))]
+		let fin_received = tcpinfo::close_wait(fd).unwrap_or(false);
+		#[cfg(not(any(target_os = "android", target_os = "linux")))]
+		let fin_received = false;
+		let remote_closed = (assumed_state == AdoptState::RemoteClosed || fin_received)
+			&& palaver::socket::unreceived(fd) == 0;
+		trace!(
+			"Connected adopt_fd {} {}",
+			format_remote(remote, &config),
+			remote_closed
+		);
+		executor.add_fd(fd);
+		executor.queue();
+		if remote_closed {
+			let send = CircularBuffer::new(config.send_buf);
+			Ok(
+				match RemoteClosed::new(fd, send, config, executor, remote, origin) {
+					RemoteClosedPoll::RemoteClosed(x) => ConnectedPoll::RemoteClosed(x),
+					RemoteClosedPoll::Killed => ConnectedPoll::Killed,
+				},
+			)
+		} else {
+			Ok(Self::new(fd, config, executor, remote, origin))
+		}
+	}
 	#[inline(always)]
 	pub fn recv_avail(&self) -> usize {
 		self.recv.as_ref().unwrap().read_available()
 	}
+	/// Free space in the receive buffer, i.e. how much more can be read from the socket before it must be drained.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn recv_space(&self) -> usize {
+		self.recv.as_ref().unwrap().write_available()
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn recv<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
@@ -669,10 +1907,364 @@ impl Connected {
 			}
 		})
 	}
+	/// Copy as many received bytes as fit into `buf`, returning how many were copied.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn recv_slice(&mut self, buf: &mut [u8], executor: &impl Notifier) -> usize {
+		let read = self.recv.as_mut().unwrap().read_slice(buf);
+		if read > 0 {
+			executor.queue();
+		}
+		read
+	}
+	/// Take exactly `n` received bytes if that many are buffered, otherwise leave the receive buffer untouched and return `None`.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn recv_exact(&mut self, n: usize, executor: &impl Notifier) -> Option<Vec<u8>> {
+		let recv = self.recv.as_mut().unwrap();
+		if recv.read_available() < n {
+			return None;
+		}
+		let mut buf = vec![0; n];
+		let read = recv.read_slice(&mut buf);
+		debug_assert_eq!(read, n);
+		if n > 0 {
+			executor.queue();
+		}
+		Some(buf)
+	}
+	/// Copy received bytes into `bufs` in order, filling each before moving to the next, returning how many were copied in total.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn recv_vectored(
+		&mut self, bufs: &mut [io::IoSliceMut<'_>], executor: &impl Notifier,
+	) -> usize {
+		let recv = self.recv.as_mut().unwrap();
+		let mut read = 0;
+		for buf in bufs {
+			if recv.read_available() == 0 {
+				break;
+			}
+			read += recv.read_slice(buf);
+		}
+		if read > 0 {
+			executor.queue();
+		}
+		read
+	}
+	// ENOBUFS and ENOMEM are from the kernel being short of memory, which may well pass, so rather than being fatal straight away they're retried after a growing delay, up to TRANSIENT_RETRIES polls in a row. Returns whether a retry was scheduled.
+	fn retry_transient(&mut self, err: nix::Error, executor: &impl Notifier) -> bool {
+		if !matches!(
+			err,
+			nix::Error::Sys(errno::Errno::ENOBUFS | errno::Errno::ENOMEM)
+		) || self.transient_errors >= TRANSIENT_RETRIES
+		{
+			return false;
+		}
+		self.transient_errors += 1;
+		let retry = time::Instant::now() + TRANSIENT_BACKOFF * self.transient_errors;
+		trace!(
+			"Connected transient err {} {:?} {:?}",
+			format_remote(self.remote, &self.config),
+			err,
+			retry
+		);
+		self.transient_retry.set(retry, executor);
+		true
+	}
+	// Kill, unless there's received data yet to be read, in which case preserve it in a Reset.
+	fn reset(mut self, err: nix::Error, executor: &impl Notifier) -> ConnectedPoll {
+		if self.recv_avail() == 0 {
+			self.kill(executor);
+			return ConnectedPoll::Killed;
+		}
+		self.send_deadline.cancel(executor);
+		self.idle_timer.cancel(executor);
+		self.transient_retry.cancel(executor);
+		self.flush_timer.cancel(executor);
+		executor.remove_fd(self.fd);
+		unistd::close(self.fd).unwrap();
+		let ret = match Reset::new(
+			self.recv.take().unwrap(),
+			err,
+			mem::take(&mut self.config),
+			executor,
+			self.remote,
+			self.origin,
+		) {
+			ResetPoll::Reset(x) => ConnectedPoll::Reset(x),
+			ResetPoll::Killed => ConnectedPoll::Killed,
+		};
+		let _ = self.send.take().unwrap();
+		mem::forget(self);
+		ret
+	}
+	/// Iterate over the currently received bytes. Unlike [`recv`](Connected::recv) this queues a poll only once, when the iterator is dropped; bytes not iterated over remain buffered.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn recv_iter<'a>(
+		&'a mut self, executor: &'a impl Notifier,
+	) -> impl Iterator<Item = u8> + 'a {
+		RecvIter {
+			recv: self.recv.as_mut().unwrap(),
+			executor,
+			read: false,
+		}
+	}
+	/// Discard all received data, both that already buffered and that received from now on, while keeping the connection sendable. This is for when the application is ignoring the read side, which would otherwise fill the receive buffer and stall.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn drain_recv(&mut self, executor: &impl Notifier) {
+		self.drain_recv = true;
+		self.recv.as_mut().unwrap().discard();
+		executor.queue();
+	}
+	/// ACK received data immediately rather than delaying it (`TCP_QUICKACK`), until the kernel reverts to delayed ACKs, which it may after any read. See [`ConnectionConfig::quick_ack`] to have it re-set after each read. Linux and Android only; a no-op elsewhere.
+	pub fn quick_ack(&mut self) {
+		set_quick_ack(self.fd);
+	}
+	/// Enable or disable receive timestamps, as reported by [`last_recv_timestamp`](Connected::last_recv_timestamp). On Linux and Android this is `SO_TIMESTAMPING`, in nanoseconds: the NIC's raw hardware timestamp where it's been configured to generate them (with `SIOCSHWTSTAMP`), and the kernel's software timestamp otherwise, falling back to `SO_TIMESTAMPNS` if the kernel refuses `SO_TIMESTAMPING`. Elsewhere it's `SO_TIMESTAMP`, in microseconds. Data received shortly after enabling may not be timestamped, as the kernel may enable timestamping asynchronously.
+	///
+	/// # Panics
+	///
+	/// If the socket option can't be set.
+	pub fn set_recv_timestamps(&mut self, enable: bool) {
+		set_recv_timestamps(self.fd, enable).unwrap();
+		self.recv_timestamps = enable;
+		if !enable {
+			self.last_recv_timestamp = None;
+		}
+	}
+	/// The kernel receive time, relative to the UNIX epoch, of the most recently received data. Only available once enabled with [`set_recv_timestamps`](Connected::set_recv_timestamps).
+	#[must_use]
+	#[inline(always)]
+	pub fn last_recv_timestamp(&self) -> Option<time::Duration> {
+		self.last_recv_timestamp
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		self.fd
+	}
+	pub(crate) fn set_remote(&mut self, remote: net::SocketAddr) {
+		self.remote = remote;
+	}
+	// for wrapping states, which hand this back once they're done with it
+	pub(crate) fn entered_state(&mut self) {
+		self.since = time::Instant::now();
+	}
+	/// Bytes received by the kernel but yet to be read into the receive buffer.
+	#[must_use]
+	#[inline(always)]
+	pub fn kernel_recv_queue(&self) -> usize {
+		palaver::socket::unreceived(self.fd)
+	}
+	/// Bytes written to the kernel but yet to be acknowledged by the remote.
+	#[must_use]
+	#[inline(always)]
+	pub fn kernel_send_queue(&self) -> usize {
+		palaver::socket::unsent(self.fd)
+	}
+	/// Whether everything sent has been written out of the send buffer and acknowledged by the remote, for protocols that want delivery confirmed before closing. Unlike [`close`](Connected::close) this doesn't shut down the write side, so more can still be sent.
+	///
+	/// Call this on each poll until it returns [`Flushed`](FlushProgress::Flushed); while waiting on acknowledgements it arranges to be polled again, as they don't necessarily trigger an event: after about the round-trip time at first, then backing off.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn flush_and_wait(&mut self, executor: &impl Notifier) -> FlushProgress {
+		let buffered = self.send.as_ref().unwrap().read_available();
+		let unacked = palaver::socket::unsent(self.fd);
+		if buffered == 0 && unacked == 0 {
+			self.flush_timer.cancel(executor);
+			self.flush_backoff = None;
+			return FlushProgress::Flushed;
+		}
+		if buffered > 0 {
+			executor.queue();
+		}
+		let now = time::Instant::now();
+		if self
+			.flush_timer
+			.instant()
+			.is_none_or(|instant| now >= instant)
+		{
+			let backoff = self.flush_backoff.map_or_else(
+				|| {
+					delivery_stats(self.fd)
+						.map_or(FLUSH_BACKOFF_MIN, |stats| stats.rtt)
+						.clamp(FLUSH_BACKOFF_MIN, FLUSH_BACKOFF_MAX)
+				},
+				|backoff| cmp::min(backoff * 2, FLUSH_BACKOFF_MAX),
+			);
+			self.flush_backoff = Some(backoff);
+			self.flush_timer.set(now + backoff, executor);
+		}
+		FlushProgress::Flushing { buffered, unacked }
+	}
+	/// Take the socket's pending error (`SO_ERROR`), if any, as with [`TcpStream::take_error`](std::net::TcpStream::take_error), for checking the connection's health on the caller's own schedule.
+	///
+	/// This clears it, so the next [`poll`](Connected::poll) may not see it: after a reset, reads then give end-of-stream rather than `ECONNRESET`. A connection that returns an error here should generally be killed.
+	///
+	/// # Errors
+	///
+	/// If `SO_ERROR` can't be read.
+	pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+		match socket::getsockopt(self.fd, socket::sockopt::SocketError).map_err(nix_to_io)? {
+			0 => Ok(None),
+			err => Ok(Some(io::Error::from_raw_os_error(err))),
+		}
+	}
+	/// The socket's file status flags (`F_GETFL`), such as `O_NONBLOCK`, for diagnosing how it's been set up.
+	///
+	/// # Errors
+	///
+	/// If `fcntl` fails.
+	pub fn fd_flags(&self) -> io::Result<fcntl::OFlag> {
+		fd_flags(self.fd).map_err(nix_to_io)
+	}
+	/// Check whether the connection is still alive, without sending any data, for instance before reusing it from a pool. This reads any pending socket error, and attempts a zero-length write; a remote close is found from `TCP_INFO` on Linux and Android, and otherwise by peeking. Anything other than [`Alive`](ProbeResult::Alive) queues a poll, so the change is acted upon: a [`Dead`](ProbeResult::Dead) connection is then reset, as though the poll had hit the error itself.
+	pub fn probe(&mut self, executor: &impl Notifier) -> ProbeResult {
+		let ret = match probe(self.fd, self.remote_closed) {
+			Ok(false) => ProbeResult::Alive,
+			Ok(true) => ProbeResult::RemoteClosed,
+			Err(err) => {
+				// the socket reports an error only once, so keep it for the next poll
+				self.probe_err = Some(err);
+				ProbeResult::Dead(nix_to_io(err))
+			}
+		};
+		if !matches!(ret, ProbeResult::Alive) {
+			trace!(
+				"Connected probe {:?} {}",
+				ret,
+				format_remote(self.remote, &self.config)
+			);
+			executor.queue();
+		}
+		ret
+	}
+	/// The CPU that processed the connection's most recent incoming packets (`SO_INCOMING_CPU`). Linux only.
+	#[must_use]
+	pub fn incoming_cpu(&self) -> Option<u32> {
+		#[cfg(target_os = "linux")]
+		{
+			sockopt::get::<libc::c_int>(self.fd, libc::SOL_SOCKET, libc::SO_INCOMING_CPU)
+				.ok()
+				.and_then(|cpu| cpu.try_into().ok())
+		}
+		#[cfg(not(target_os = "linux"))]
+		{
+			None
+		}
+	}
+	/// The ID of the NAPI context (broadly, the NIC receive queue) that processed the connection's most recent incoming packets (`SO_INCOMING_NAPI_ID`), if known. Linux only.
+	#[must_use]
+	pub fn incoming_napi_id(&self) -> Option<u32> {
+		#[cfg(target_os = "linux")]
+		{
+			sockopt::get::<libc::c_uint>(self.fd, libc::SOL_SOCKET, libc::SO_INCOMING_NAPI_ID)
+				.ok()
+				.filter(|&id| id != 0)
+		}
+		#[cfg(not(target_os = "linux"))]
+		{
+			None
+		}
+	}
+	/// The bound on the advertised receive window (`TCP_WINDOW_CLAMP`), as set by [`ConnectionConfig::window_clamp`] or otherwise chosen by the kernel. Linux only.
+	#[must_use]
+	pub fn window_clamp(&self) -> Option<u32> {
+		#[cfg(target_os = "linux")]
+		{
+			sockopt::get::<libc::c_int>(self.fd, libc::IPPROTO_TCP, libc::TCP_WINDOW_CLAMP)
+				.ok()
+				.and_then(|clamp| clamp.try_into().ok())
+		}
+		#[cfg(not(target_os = "linux"))]
+		{
+			None
+		}
+	}
+	pub(crate) fn buffers(&mut self) -> (&mut CircularBuffer<u8>, &mut CircularBuffer<u8>) {
+		(self.recv.as_mut().unwrap(), self.send.as_mut().unwrap())
+	}
+	/// Kill the connection if nothing is received for `read`, or if data is waiting to be sent but none is sent and acknowledged for `write`. Passing `None` for both clears it, as does leaving the `Connected` state.
+	pub fn set_idle_timeout(
+		&mut self, read: Option<time::Duration>, write: Option<time::Duration>,
+		executor: &impl Notifier,
+	) {
+		self.idle = if read.is_some() || write.is_some() {
+			let now = time::Instant::now();
+			Some(IdleTimeout {
+				read,
+				write,
+				last_read: now,
+				last_write: now,
+				unsent: palaver::socket::unsent(self.fd),
+			})
+		} else {
+			None
+		};
+		self.idle_timer.cancel(executor);
+		// the next poll schedules the timer
+		executor.queue();
+	}
+	// Note activity since the last poll, returning when the connection will be idle if there's none further.
+	fn idle_deadline(&mut self, wrote: bool, read: bool) -> Option<time::Instant> {
+		let idle = self.idle.as_mut()?;
+		let now = time::Instant::now();
+		if read {
+			idle.last_read = now;
+		}
+		let unsent = palaver::socket::unsent(self.fd);
+		let send_pending = self.send.as_ref().unwrap().read_available() > 0 || unsent > 0;
+		if wrote || unsent < idle.unsent || !send_pending {
+			idle.last_write = now;
+		}
+		idle.unsent = unsent;
+		let remote_closed = self.remote_closed;
+		let read_deadline = idle
+			.read
+			.filter(|_| !remote_closed)
+			.map(|timeout| idle.last_read + timeout);
+		let write_deadline = idle.write.map(|timeout| idle.last_write + timeout);
+		read_deadline.into_iter().chain(write_deadline).min()
+	}
+	/// Kill the connection, with a reset, if everything sent so far hasn't been acknowledged by the remote within `timeout`. This is cleared once it has been, or when leaving the `Connected` state.
+	pub fn set_send_deadline(&mut self, timeout: time::Duration, executor: &impl Notifier) {
+		self.send_deadline
+			.set(time::Instant::now() + timeout, executor);
+	}
 	#[inline(always)]
 	pub fn send_avail(&self) -> usize {
 		self.send.as_ref().unwrap().write_available()
 	}
+	/// Bytes in the send buffer not yet written to the socket.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn send_buffered(&self) -> usize {
+		self.send.as_ref().unwrap().read_available()
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn send<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce(u8) + 'a> {
@@ -683,29 +2275,304 @@ impl Connected {
 			}
 		})
 	}
+	/// Queue as much of `buf` as fits in the send buffer, returning how many bytes were queued. With [`ConnectionConfig::send_direct`], more than fits in an empty send buffer is written straight to the socket first.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn send_slice(&mut self, buf: &[u8], executor: &impl Notifier) -> usize {
+		let send_buf = self.send.as_mut().unwrap();
+		let mut sent = 0;
+		if self.config.send_direct && buf.len() > send_buf.capacity() {
+			sent = send_direct(self.fd, send_buf, buf);
+		}
+		sent += send_buf.write_slice(&buf[sent..]);
+		if sent > 0 {
+			executor.queue();
+		}
+		sent
+	}
+	/// Send up to `len` bytes of `file` from `offset` with `sendfile(2)`, bypassing the send buffer, returning how many bytes were sent. This is 0 if the socket is full, or if data already in the send buffer couldn't be flushed ahead of it. Linux, Android, macOS and iOS only.
+	///
+	/// # Errors
+	///
+	/// If writing out the send buffer or `sendfile` fails, other than with `EAGAIN`, or `InvalidInput` if `offset` is out of range.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn send_file(
+		&mut self, file: &fs::File, offset: u64, len: usize, executor: &impl Notifier,
+	) -> io::Result<usize> {
+		// preserve ordering with what's been sent through the send buffer
+		let _ = self
+			.send
+			.as_mut()
+			.unwrap()
+			.read_to_fd(self.fd)
+			.map_err(nix_to_io)?;
+		if self.send_buffered() > 0 || len == 0 {
+			return Ok(0);
+		}
+		let offset = offset
+			.try_into()
+			.map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+		let sent = match sendfile(self.fd, file.as_raw_fd(), offset, len) {
+			Ok(sent) => sent,
+			Err(nix::Error::Sys(errno::Errno::EAGAIN)) => 0,
+			Err(err) => return Err(nix_to_io(err)),
+		};
+		if sent > 0 {
+			executor.queue();
+		}
+		Ok(sent)
+	}
+	/// Like [`send_slice`](Connected::send_slice), also returning the [`send_avail`](Connected::send_avail) remaining afterwards.
+	pub fn send_slice_report(&mut self, buf: &[u8], executor: &impl Notifier) -> (usize, usize) {
+		let sent = self.send_slice(buf, executor);
+		(sent, self.send_avail())
+	}
+	/// Whether more sent now would soon go out on the network, rather than only queue behind data the remote hasn't yet taken: there's [`send_avail`](Connected::send_avail), and what's in the send buffer plus the [`kernel_send_queue`](Connected::kernel_send_queue) is within `SO_SNDBUF`. This is approximate, as the kernel counts its bookkeeping overhead against `SO_SNDBUF` too.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	#[must_use]
+	pub fn can_make_progress(&self) -> bool {
+		let send = self.send.as_ref().unwrap();
+		if send.write_available() == 0 {
+			return false;
+		}
+		let queued = send.read_available() + palaver::socket::unsent(self.fd);
+		socket::getsockopt(self.fd, socket::sockopt::SndBuf).is_ok_and(|sndbuf| queued < sndbuf)
+	}
+	/// Whether [`close`](Connected::close) would reach [`Closed`](LocalClosedPoll::Closed) straight away: the remote has closed and everything received has been read, and everything sent has left both the send buffer and the kernel.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	#[must_use]
+	pub fn close_is_immediate(&self) -> bool {
+		self.remote_closed
+			&& self.recv.as_ref().unwrap().read_available() == 0
+			&& self.send.as_ref().unwrap().read_available() == 0
+			&& palaver::socket::unsent(self.fd) == 0
+	}
+	/// Like [`close`](Connected::close), but with the given [`ConnectionConfig::close_timeout`], after which the connection is reset.
+	pub fn close_with_timeout(
+		mut self, timeout: time::Duration, executor: &impl Notifier,
+	) -> LocalClosedPoll {
+		self.config.close_timeout = Some(timeout);
+		self.close(executor)
+	}
 	pub fn close(mut self, executor: &impl Notifier) -> LocalClosedPoll {
+		debug_assert!(!self.poisoned, "Connected closed after kill_in_place");
 		// TODO: simple return type, don't poll
+		self.send_deadline.cancel(executor);
+		self.idle_timer.cancel(executor);
+		self.transient_retry.cancel(executor);
+		self.flush_timer.cancel(executor);
 		let ret = LocalClosed::new(
 			self.fd,
 			self.send.take().unwrap(),
 			self.recv.take().unwrap(),
 			self.remote_closed,
+			self.recv_timestamps,
+			self.last_recv_timestamp,
+			close_deadline(&self.config, executor),
+			mem::take(&mut self.config),
 			executor,
 			self.remote,
+			self.origin,
 		);
 		mem::forget(self);
 		ret
 	}
-	pub fn kill(mut self, executor: &impl Notifier) {
+	/// Stop managing this connection, returning the still-open fd along with the data yet to be sent to it, and the data received from it yet to be read.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn into_raw_fd(mut self, executor: &impl Notifier) -> (Fd, Vec<u8>, Vec<u8>) {
+		self.send_deadline.cancel(executor);
+		self.idle_timer.cancel(executor);
+		self.transient_retry.cancel(executor);
+		self.flush_timer.cancel(executor);
+		executor.remove_fd(self.fd);
+		let ret = (
+			self.fd,
+			self.send.take().unwrap().into_vec(),
+			self.recv.take().unwrap().into_vec(),
+		);
+		let _ = mem::take(&mut self.config);
+		mem::forget(self);
+		ret
+	}
+	/// Like [`kill`](Connected::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "Connected killed twice");
+		self.send_deadline.cancel(executor);
+		self.idle_timer.cancel(executor);
+		self.transient_retry.cancel(executor);
+		self.flush_timer.cancel(executor);
 		executor.remove_fd(self.fd);
 		unistd::close(self.fd).unwrap();
 		let _ = self.send.take().unwrap();
 		let _ = self.recv.take().unwrap();
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The maximum segment size (`TCP_MAXSEG`) in effect, as negotiated with the remote and limited by the path MTU.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn mss(&self) -> io::Result<u32> {
+		mss(self.fd)
+	}
+	/// The round-trip time, retransmissions and delivery rate as measured by the kernel, for adapting how fast to send. Returns `ENOSYS` on platforms other than Linux, Android, macOS and iOS.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn delivery_stats(&self) -> io::Result<DeliveryStats> {
+		delivery_stats(self.fd).map_err(nix_to_io)
+	}
+	/// The path MTU discovered by the kernel (`IP_MTU`, or `IPV6_MTU` for IPv6 sockets), for sizing writes to avoid fragmentation. Returns `ENOSYS` on platforms other than Linux.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn path_mtu(&self) -> io::Result<u32> {
+		path_mtu(self.fd).map_err(nix_to_io)
+	}
+	/// The cursors and fill of the send and receive buffers, for profiling, such as to see why a workload makes more syscalls than expected. Only with the `diagnostics` feature.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	#[must_use]
+	#[cfg(feature = "diagnostics")]
+	pub fn buffer_debug(&self) -> BufferDebug {
+		BufferDebug {
+			send: self.send.as_ref().unwrap().debug(),
+			recv: self.recv.as_ref().unwrap().debug(),
+		}
+	}
+	/// What's in the kernel's send buffer: `TIOCOUTQ` and `SIOCOUTQNSD` on Linux and Android, and `tcpi_snd_sbbytes` on macOS and iOS. Returns `ENOSYS` elsewhere.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn send_queue(&self) -> io::Result<SendQueue> {
+		send_queue(self.fd).map_err(nix_to_io)
+	}
+	/// Whether Nagle's algorithm looks to be holding back data: `TCP_NODELAY` is off, and less than a segment is waiting to be sent while earlier data is yet to be acknowledged. A heuristic, for diagnosing batching latency and deciding whether to set `TCP_NODELAY`; always `false` on platforms other than Linux and Android, which don't report the unsent bytes.
+	#[must_use]
+	pub fn nagle_held(&self) -> bool {
+		if socket::getsockopt(self.fd, socket::sockopt::TcpNoDelay).unwrap_or(true) {
+			return false;
+		}
+		match (send_queue(self.fd), mss(self.fd)) {
+			(Ok(queue), Ok(mss)) => {
+				queue
+					.unsent
+					.is_some_and(|unsent| unsent > 0 && unsent < mss as usize)
+					&& queue.in_flight().is_some_and(|in_flight| in_flight > 0)
+			}
+			_ => false,
+		}
+	}
+	/// The sender's congestion window, in segments. Read straight after connecting, this is the initial window, for checking that route-level tuning (`ip route ... initcwnd` on Linux) has taken effect; it isn't settable per socket. Returns `ENOSYS` on platforms other than Linux, Android, macOS and iOS.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn congestion_window(&self) -> io::Result<u32> {
+		congestion_window(self.fd).map_err(nix_to_io)
+	}
+	/// Reallocate both buffers to [`ConnectionConfig::idle_buf`], to reclaim memory while the connection is idle. This only happens if both are empty, so that nothing is lost; returns whether it did.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn shrink_buffers(&mut self) -> bool {
+		let (send, recv) = (self.send.as_mut().unwrap(), self.recv.as_mut().unwrap());
+		if send.read_available() != 0 || recv.read_available() != 0 {
+			return false;
+		}
+		for buf in [send, recv] {
+			if buf.capacity() > self.config.idle_buf {
+				buf.resize(self.config.idle_buf);
+			}
+		}
+		true
+	}
+	/// Restore buffers reduced by [`shrink_buffers`](Connected::shrink_buffers) to [`ConnectionConfig::send_buf`] and [`ConnectionConfig::recv_buf`]. Buffered data is kept.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	pub fn grow_buffers(&mut self, executor: &impl Notifier) {
+		let (send, recv) = (self.send.as_mut().unwrap(), self.recv.as_mut().unwrap());
+		if send.capacity() < self.config.send_buf {
+			send.resize(self.config.send_buf);
+		}
+		if recv.capacity() < self.config.recv_buf {
+			recv.resize(self.config.recv_buf);
+			// resume reading if it had stopped on the buffer being full
+			executor.queue();
+		}
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Connected::kill_in_place).
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		let recv = self.recv.as_ref().unwrap();
+		Interest {
+			read: !self.remote_closed
+				&& (self.drain_recv
+					|| recv.write_available() > 0
+					|| self
+						.config
+						.recv_full
+						.max_capacity()
+						.is_some_and(|max| recv.capacity() < max)
+					// woken as more arrives, to check it against the threshold
+					|| self.config.recv_full.reset_threshold().is_some()),
+			write: self.send.as_ref().unwrap().read_available() > 0,
+		}
+	}
+}
+impl StateInfo for Connected {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		self.origin
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for Connected {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop Connected");
 	}
 }
@@ -717,11 +2584,62 @@ impl fmt::Debug for Connected {
 			.field("send", &self.send)
 			.field("recv", &self.recv)
 			.field("remote_closed", &self.remote_closed)
+			.field("recv_timestamps", &self.recv_timestamps)
+			.field("last_recv_timestamp", &self.last_recv_timestamp)
+			.field("drain_recv", &self.drain_recv)
+			.field("send_deadline", &self.send_deadline)
+			.field("idle", &self.idle)
+			.field("idle_timer", &self.idle_timer)
+			.field("transient_errors", &self.transient_errors)
+			.field("transient_retry", &self.transient_retry)
+			.field("flush_timer", &self.flush_timer)
+			.field("flush_backoff", &self.flush_backoff)
+			.field("probe_err", &self.probe_err)
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("origin", &self.origin)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
 
+struct RecvIter<'a, N: Notifier> {
+	recv: &'a mut CircularBuffer<u8>,
+	executor: &'a N,
+	read: bool,
+}
+impl<N: Notifier> Iterator for RecvIter<'_, N> {
+	type Item = u8;
+	fn next(&mut self) -> Option<u8> {
+		let byte = self.recv.read().map(|x| x());
+		self.read |= byte.is_some();
+		byte
+	}
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let avail = self.recv.read_available();
+		(avail, Some(avail))
+	}
+}
+impl<N: Notifier> Drop for RecvIter<'_, N> {
+	fn drop(&mut self) {
+		if self.read {
+			self.executor.queue();
+		}
+	}
+}
+
+// Per Connected::set_idle_timeout.
+#[derive(Debug)]
+struct IdleTimeout {
+	read: Option<time::Duration>,
+	write: Option<time::Duration>,
+	last_read: time::Instant,
+	last_write: time::Instant,
+	// kernel send queue as of the last poll, a decrease in which is progress
+	unsent: usize,
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Debug)]
@@ -733,24 +2651,53 @@ pub struct RemoteClosed {
 	fd: Fd,
 	send: Option<CircularBuffer<u8>>,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	origin: Origin,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl RemoteClosed {
 	fn new(
-		fd: Fd, send: CircularBuffer<u8>, executor: &impl Notifier, remote: net::SocketAddr,
+		fd: Fd, send: CircularBuffer<u8>, config: ConnectionConfig, executor: &impl Notifier,
+		remote: net::SocketAddr, origin: Origin,
 	) -> RemoteClosedPoll {
 		Self {
 			fd,
 			send: Some(send),
 			remote,
+			config,
+			since: time::Instant::now(),
+			origin,
+			poisoned: false,
 		}
 		.poll(executor)
 	}
 	pub fn poll(mut self, executor: &impl Notifier) -> RemoteClosedPoll {
-		assert_eq!(palaver::socket::unreceived(self.fd), 0);
+		debug_assert!(!self.poisoned, "RemoteClosed polled after kill_in_place");
+		let discarded = discard_unreceived(self.fd);
+		if discarded > 0 {
+			trace!(
+				"RemoteClosed discarded {} bytes after close {}",
+				discarded,
+				format_remote(self.remote, &self.config)
+			);
+		}
+		let send_full = self.send_avail() == 0;
 		match self.send.as_mut().unwrap().read_to_fd(self.fd) {
-			Ok(_written) => RemoteClosedPoll::RemoteClosed(self),
+			Ok(written) => {
+				if send_full && written > 0 {
+					// rewake any writer blocked on the full send buffer
+					executor.queue();
+				}
+				RemoteClosedPoll::RemoteClosed(self)
+			}
 			Err(err) => {
-				trace!("RemoteClosed err {} {:?}", format_remote(self.remote), err,);
+				trace!(
+					"RemoteClosed err {} {:?}",
+					format_remote(self.remote, &self.config),
+					err,
+				);
 				self.kill(executor);
 				RemoteClosedPoll::Killed
 			}
@@ -760,6 +2707,16 @@ impl RemoteClosed {
 	pub fn send_avail(&self) -> usize {
 		self.send.as_ref().unwrap().write_available()
 	}
+	/// Bytes in the send buffer not yet written to the socket.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](RemoteClosed::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn send_buffered(&self) -> usize {
+		self.send.as_ref().unwrap().read_available()
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn send<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce(u8) + 'a> {
@@ -770,27 +2727,117 @@ impl RemoteClosed {
 			}
 		})
 	}
+	/// Queue as much of `buf` as fits in the send buffer, returning how many bytes were queued.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](RemoteClosed::kill_in_place).
+	pub fn send_slice(&mut self, buf: &[u8], executor: &impl Notifier) -> usize {
+		let sent = self.send.as_mut().unwrap().write_slice(buf);
+		if sent > 0 {
+			executor.queue();
+		}
+		sent
+	}
+	/// Whether [`close`](RemoteClosed::close) would reach [`Closed`](ClosingPoll::Closed) straight away: everything sent has left both the send buffer and the kernel.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](RemoteClosed::kill_in_place).
+	#[must_use]
+	pub fn close_is_immediate(&self) -> bool {
+		self.send.as_ref().unwrap().read_available() == 0 && palaver::socket::unsent(self.fd) == 0
+	}
+	/// Like [`close`](RemoteClosed::close), but with the given [`ConnectionConfig::close_timeout`], after which the connection is reset.
+	pub fn close_with_timeout(
+		mut self, timeout: time::Duration, executor: &impl Notifier,
+	) -> ClosingPoll {
+		self.config.close_timeout = Some(timeout);
+		self.close(executor)
+	}
 	pub fn close(mut self, executor: &impl Notifier) -> ClosingPoll {
+		debug_assert!(!self.poisoned, "RemoteClosed closed after kill_in_place");
 		// TODO: simple return type, don't poll
 		let ret = Closing::new(
 			self.fd,
 			self.send.take().unwrap(),
 			false,
+			close_deadline(&self.config, executor),
+			mem::take(&mut self.config),
 			executor,
 			self.remote,
+			self.origin,
 		);
 		mem::forget(self);
 		ret
 	}
-	pub fn kill(mut self, executor: &impl Notifier) {
+	/// Like [`kill`](RemoteClosed::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "RemoteClosed killed twice");
 		executor.remove_fd(self.fd);
 		unistd::close(self.fd).unwrap();
 		let _ = self.send.take().unwrap();
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The maximum segment size (`TCP_MAXSEG`) in effect, as negotiated with the remote and limited by the path MTU.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn mss(&self) -> io::Result<u32> {
+		mss(self.fd)
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](RemoteClosed::kill_in_place).
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		Interest {
+			read: false,
+			write: self.send.as_ref().unwrap().read_available() > 0,
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		self.fd
+	}
+}
+impl StateInfo for RemoteClosed {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		self.origin
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for RemoteClosed {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop RemoteClosed");
 	}
 }
@@ -801,6 +2848,10 @@ impl fmt::Debug for RemoteClosed {
 			.field("socket", &socketstat::socketstat(self.fd))
 			.field("send", &self.send)
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("origin", &self.origin)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
@@ -811,6 +2862,7 @@ impl fmt::Debug for RemoteClosed {
 pub enum LocalClosedPoll {
 	LocalClosed(LocalClosed),
 	Closing(Closing),
+	Reset(Reset),
 	Closed,
 	Killed,
 }
@@ -819,82 +2871,153 @@ pub struct LocalClosed {
 	send: Option<CircularBuffer<u8>>,
 	recv: Option<CircularBuffer<u8>>,
 	remote_closed: bool,
+	recv_timestamps: bool,
+	last_recv_timestamp: Option<time::Duration>,
 	local_closed_given: bool,
+	close_deadline: Timer,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	origin: Origin,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl LocalClosed {
 	fn new(
 		fd: Fd, send: CircularBuffer<u8>, recv: CircularBuffer<u8>, remote_closed: bool,
-		executor: &impl Notifier, remote: net::SocketAddr,
+		recv_timestamps: bool, last_recv_timestamp: Option<time::Duration>, close_deadline: Timer,
+		config: ConnectionConfig, executor: &impl Notifier, remote: net::SocketAddr,
+		origin: Origin,
 	) -> LocalClosedPoll {
 		Self {
 			fd,
 			send: Some(send),
 			recv: Some(recv),
 			remote_closed,
+			recv_timestamps,
+			last_recv_timestamp,
 			local_closed_given: false,
+			close_deadline,
 			remote,
+			config,
+			since: time::Instant::now(),
+			origin,
+			poisoned: false,
 		}
 		.poll(executor)
 	}
+	#[allow(clippy::too_many_lines)]
 	pub fn poll(mut self, executor: &impl Notifier) -> LocalClosedPoll {
+		debug_assert!(!self.poisoned, "LocalClosed polled after kill_in_place");
 		if self.local_closed_given && self.remote_closed {
 			let x = socket::getsockopt(self.fd, socket::sockopt::SocketError).unwrap();
 			if x != 0 {
+				let err = nix::Error::Sys(errno::Errno::from_i32(x));
 				trace!(
 					"LocalClosed err {} {:?}",
-					format_remote(self.remote),
-					errno::Errno::from_i32(x),
+					format_remote(self.remote, &self.config),
+					err,
 				);
-				self.kill(executor);
-				return LocalClosedPoll::Killed;
+				return self.reset(err, executor);
 			}
 		}
 		if !self.local_closed_given {
 			match self.send.as_mut().unwrap().read_to_fd(self.fd) {
 				Ok(_written) => (),
 				Err(err) => {
-					trace!("LocalClosed err {} {:?}", format_remote(self.remote), err,);
-					self.kill(executor);
-					return LocalClosedPoll::Killed;
+					trace!(
+						"LocalClosed err {} {:?}",
+						format_remote(self.remote, &self.config),
+						err,
+					);
+					return self.reset(err, executor);
 				}
 			}
 		}
 		if !self.remote_closed {
-			match self.recv.as_mut().unwrap().write_from_fd(self.fd) {
+			let timestamp = if self.recv_timestamps {
+				Some(&mut self.last_recv_timestamp)
+			} else {
+				None
+			};
+			match self.recv.as_mut().unwrap().write_from_fd(
+				self.fd,
+				timestamp,
+				self.config.recv_full.max_capacity(),
+			) {
 				Ok((_read, false)) => (),
 				Ok((_read, true)) => {
-					trace!("LocalClosed got closed {}", format_remote(self.remote));
+					trace!(
+						"LocalClosed got closed {}",
+						format_remote(self.remote, &self.config)
+					);
 					#[cfg(any(target_os = "macos", target_os = "ios"))]
-					assert_ne!(sockstate::sockstate(self.fd), sockstate::TcpState::ESTABLISHED, "this is a bug in macOS; see tcp_typed/src/socket_forwarder.rs for a mitigation");
+					{
+						let spurious = sockstate::eof_while_established(self.fd);
+						debug_assert!(!spurious, "this is a bug in macOS; see tcp_typed/src/socket_forwarder.rs for a mitigation");
+						if spurious {
+							// the stream has nonetheless ended, so treat it as closed rather than stalling
+							trace!(
+								"LocalClosed closed while ESTABLISHED {}",
+								format_remote(self.remote, &self.config)
+							);
+						}
+					}
 					self.remote_closed = true;
 				}
 				Err(err) => {
-					trace!("LocalClosed err {} {:?}", format_remote(self.remote), err,);
-					self.kill(executor);
-					return LocalClosedPoll::Killed;
+					trace!(
+						"LocalClosed err {} {:?}",
+						format_remote(self.remote, &self.config),
+						err,
+					);
+					return self.reset(err, executor);
 				}
 			}
+			if recv_overflowed(self.fd, self.recv.as_ref().unwrap(), self.config.recv_full) {
+				trace!(
+					"LocalClosed recv overflow {}",
+					format_remote(self.remote, &self.config)
+				);
+				set_reset_on_close(self.fd);
+				self.kill(executor);
+				return LocalClosedPoll::Killed;
+			}
 		}
 		if !self.local_closed_given && self.send.as_mut().unwrap().read_available() == 0 {
 			match socket::shutdown(self.fd, socket::Shutdown::Write) {
 				Ok(()) => self.local_closed_given = true,
 				Err(err) => {
-					trace!("LocalClosed err {} {:?}", format_remote(self.remote), err,);
-					self.kill(executor);
-					return LocalClosedPoll::Killed;
+					trace!(
+						"LocalClosed err {} {:?}",
+						format_remote(self.remote, &self.config),
+						err,
+					);
+					return self.reset(err, executor);
 				}
 			}
 		}
 		if !self.remote_closed || self.recv.as_mut().unwrap().read_available() > 0 {
+			if close_deadline_passed(&self.close_deadline) {
+				trace!(
+					"LocalClosed close timeout {}",
+					format_remote(self.remote, &self.config)
+				);
+				set_reset_on_close(self.fd);
+				self.kill(executor);
+				return LocalClosedPoll::Killed;
+			}
 			LocalClosedPoll::LocalClosed(self)
 		} else {
 			let ret = match Closing::new(
 				self.fd,
 				self.send.take().unwrap(),
 				self.local_closed_given,
+				mem::replace(&mut self.close_deadline, Timer::new()),
+				mem::take(&mut self.config),
 				executor,
 				self.remote,
+				self.origin,
 			) {
 				ClosingPoll::Closing(x) => LocalClosedPoll::Closing(x),
 				ClosingPoll::Closed => LocalClosedPoll::Closed,
@@ -909,6 +3032,16 @@ impl LocalClosed {
 	pub fn recv_avail(&self) -> usize {
 		self.recv.as_ref().unwrap().read_available()
 	}
+	/// Free space in the receive buffer, i.e. how much more can be read from the socket before it must be drained.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](LocalClosed::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn recv_space(&self) -> usize {
+		self.recv.as_ref().unwrap().write_available()
+	}
 	#[must_use]
 	#[inline(always)]
 	pub fn recv<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
@@ -920,16 +3053,142 @@ impl LocalClosed {
 			}
 		})
 	}
-	pub fn kill(mut self, executor: &impl Notifier) {
+	/// As [`Connected::recv_slice`].
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](LocalClosed::kill_in_place).
+	pub fn recv_slice(&mut self, buf: &mut [u8], executor: &impl Notifier) -> usize {
+		let read = self.recv.as_mut().unwrap().read_slice(buf);
+		if read > 0 {
+			executor.queue();
+		}
+		read
+	}
+	/// As [`Connected::last_recv_timestamp`], carried over from the [`Connected`] this was closed from.
+	#[must_use]
+	#[inline(always)]
+	pub fn last_recv_timestamp(&self) -> Option<time::Duration> {
+		self.last_recv_timestamp
+	}
+	// Kill, unless there's received data yet to be read, in which case preserve it in a Reset.
+	fn reset(mut self, err: nix::Error, executor: &impl Notifier) -> LocalClosedPoll {
+		if self.recv_avail() == 0 {
+			self.kill(executor);
+			return LocalClosedPoll::Killed;
+		}
+		self.close_deadline.cancel(executor);
 		executor.remove_fd(self.fd);
 		unistd::close(self.fd).unwrap();
+		let ret = match Reset::new(
+			self.recv.take().unwrap(),
+			err,
+			mem::take(&mut self.config),
+			executor,
+			self.remote,
+			self.origin,
+		) {
+			ResetPoll::Reset(x) => LocalClosedPoll::Reset(x),
+			ResetPoll::Killed => LocalClosedPoll::Killed,
+		};
 		let _ = self.send.take().unwrap();
-		let _ = self.recv.take().unwrap();
 		mem::forget(self);
+		ret
+	}
+	/// Like [`kill`](LocalClosed::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "LocalClosed killed twice");
+		self.close_deadline.cancel(executor);
+		executor.remove_fd(self.fd);
+		unistd::close(self.fd).unwrap();
+		let _ = self.send.take().unwrap();
+		let _ = self.recv.take().unwrap();
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// Bytes in the send buffer not yet written to the socket.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](LocalClosed::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn send_buffered(&self) -> usize {
+		self.send.as_ref().unwrap().read_available()
+	}
+	/// Whether the write side has been shut down, i.e. the FIN has been handed to the kernel, which sends it after anything still in its send queue. This happens once the send buffer has been flushed.
+	#[must_use]
+	#[inline(always)]
+	pub fn local_fin_sent(&self) -> bool {
+		self.local_closed_given
+	}
+	/// The maximum segment size (`TCP_MAXSEG`) in effect, as negotiated with the remote and limited by the path MTU.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn mss(&self) -> io::Result<u32> {
+		mss(self.fd)
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](LocalClosed::kill_in_place).
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		let recv = self.recv.as_ref().unwrap();
+		Interest {
+			read: !self.remote_closed
+				&& (recv.write_available() > 0
+					|| self
+						.config
+						.recv_full
+						.max_capacity()
+						.is_some_and(|max| recv.capacity() < max)
+					// woken as more arrives, to check it against the threshold
+					|| self.config.recv_full.reset_threshold().is_some()),
+			write: !self.local_closed_given,
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		self.fd
+	}
+}
+impl StateInfo for LocalClosed {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		self.origin
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for LocalClosed {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop LocalClosed");
 	}
 }
@@ -941,8 +3200,156 @@ impl fmt::Debug for LocalClosed {
 			.field("send", &self.send)
 			.field("recv", &self.recv)
 			.field("remote_closed", &self.remote_closed)
+			.field("recv_timestamps", &self.recv_timestamps)
+			.field("last_recv_timestamp", &self.last_recv_timestamp)
 			.field("local_closed_given", &self.local_closed_given)
+			.field("close_deadline", &self.close_deadline)
+			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("origin", &self.origin)
+			.field("poisoned", &self.poisoned)
+			.finish()
+	}
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum ResetPoll {
+	Reset(Reset),
+	Killed,
+}
+/// Errored (for example reset by the remote) with received data yet to be read. The socket has been closed; once the data has been read this becomes killed.
+pub struct Reset {
+	recv: Option<CircularBuffer<u8>>,
+	error: nix::Error,
+	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	origin: Origin,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
+}
+impl Reset {
+	fn new(
+		recv: CircularBuffer<u8>, error: nix::Error, config: ConnectionConfig,
+		executor: &impl Notifier, remote: net::SocketAddr, origin: Origin,
+	) -> ResetPoll {
+		Self {
+			recv: Some(recv),
+			error,
+			remote,
+			config,
+			since: time::Instant::now(),
+			origin,
+			poisoned: false,
+		}
+		.poll(executor)
+	}
+	pub fn poll(self, executor: &impl Notifier) -> ResetPoll {
+		debug_assert!(!self.poisoned, "Reset polled after kill_in_place");
+		if self.recv_avail() > 0 {
+			ResetPoll::Reset(self)
+		} else {
+			trace!("Reset drained {}", format_remote(self.remote, &self.config));
+			self.kill(executor);
+			ResetPoll::Killed
+		}
+	}
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Reset::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn recv_avail(&self) -> usize {
+		self.recv.as_ref().unwrap().read_available()
+	}
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Reset::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn recv<'a>(&'a mut self, executor: &'a impl Notifier) -> Option<impl FnOnce() -> u8 + 'a> {
+		self.recv.as_mut().unwrap().read().map(|x| {
+			move || {
+				let ret = x();
+				executor.queue();
+				ret
+			}
+		})
+	}
+	/// As [`Connected::recv_slice`].
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Reset::kill_in_place).
+	pub fn recv_slice(&mut self, buf: &mut [u8], executor: &impl Notifier) -> usize {
+		let read = self.recv.as_mut().unwrap().read_slice(buf);
+		if read > 0 {
+			executor.queue();
+		}
+		read
+	}
+	/// The error that ended the connection.
+	#[must_use]
+	#[inline(always)]
+	pub fn error(&self) -> nix::Error {
+		self.error
+	}
+	/// Like [`kill`](Reset::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed.
+	pub fn kill_in_place(&mut self, _executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "Reset killed twice");
+		let _ = self.recv.take().unwrap();
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+}
+impl StateInfo for Reset {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		self.origin
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
+	}
+}
+impl Drop for Reset {
+	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
+		panic!("Don't drop Reset");
+	}
+}
+impl fmt::Debug for Reset {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("Reset")
+			.field("recv", &self.recv)
+			.field("error", &self.error)
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("origin", &self.origin)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
@@ -960,26 +3367,52 @@ pub struct Closing {
 	send: Option<CircularBuffer<u8>>,
 	local_closed_given: bool,
 	remote: net::SocketAddr,
+	config: ConnectionConfig,
+	since: time::Instant,
+	origin: Origin,
+	timer: Timer,
+	close_deadline: Timer,
+	// set by kill_in_place, after which this is inert and can be dropped
+	poisoned: bool,
 }
 impl Closing {
 	fn new(
-		fd: Fd, send: CircularBuffer<u8>, local_closed_given: bool, executor: &impl Notifier,
-		remote: net::SocketAddr,
+		fd: Fd, send: CircularBuffer<u8>, local_closed_given: bool, close_deadline: Timer,
+		config: ConnectionConfig, executor: &impl Notifier, remote: net::SocketAddr,
+		origin: Origin,
 	) -> ClosingPoll {
 		Self {
 			fd,
 			send: Some(send),
 			local_closed_given,
 			remote,
+			config,
+			since: time::Instant::now(),
+			origin,
+			timer: Timer::new(),
+			close_deadline,
+			poisoned: false,
 		}
 		.poll(executor)
 	}
 	pub fn poll(mut self, executor: &impl Notifier) -> ClosingPoll {
-		assert_eq!(palaver::socket::unreceived(self.fd), 0);
+		debug_assert!(!self.poisoned, "Closing polled after kill_in_place");
+		let discarded = discard_unreceived(self.fd);
+		if discarded > 0 {
+			trace!(
+				"Closing discarded {} bytes after close {}",
+				discarded,
+				format_remote(self.remote, &self.config)
+			);
+		}
 		match self.send.as_mut().unwrap().read_to_fd(self.fd) {
 			Ok(_written) => (),
 			Err(err) => {
-				trace!("Closing err {} {:?}", format_remote(self.remote), err);
+				trace!(
+					"Closing err {} {:?}",
+					format_remote(self.remote, &self.config),
+					err
+				);
 				self.kill(executor);
 				return ClosingPoll::Killed;
 			}
@@ -990,7 +3423,7 @@ impl Closing {
 				Err(err) => {
 					trace!(
 						"Closing shutdown err {} {:?}",
-						format_remote(self.remote),
+						format_remote(self.remote, &self.config),
 						err,
 					);
 					self.kill(executor);
@@ -1000,28 +3433,124 @@ impl Closing {
 		}
 		if self.local_closed_given {
 			if palaver::socket::unsent(self.fd) == 0 {
-				trace!("Closing close {}", format_remote(self.remote));
+				trace!("Closing close {}", format_remote(self.remote, &self.config));
+				self.timer.cancel(executor);
+				self.close_deadline.cancel(executor);
 				executor.remove_fd(self.fd);
 				unistd::close(self.fd).unwrap();
 				let _ = self.send.take().unwrap();
+				let _ = mem::take(&mut self.config);
 				mem::forget(self);
 				return ClosingPoll::Closed;
 			} else {
-				let _ =
-					executor.add_instant(time::Instant::now() + time::Duration::new(0, 1_000_000));
+				self.timer.set_unless_pending(
+					time::Instant::now() + time::Duration::new(0, 1_000_000),
+					executor,
+				);
 			}
 		}
+		if close_deadline_passed(&self.close_deadline) {
+			trace!(
+				"Closing close timeout {}",
+				format_remote(self.remote, &self.config)
+			);
+			set_reset_on_close(self.fd);
+			self.kill(executor);
+			return ClosingPoll::Killed;
+		}
 		ClosingPoll::Closing(self)
 	}
-	pub fn kill(mut self, executor: &impl Notifier) {
+	/// Like [`kill`](Closing::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed, or closing the socket fails.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		debug_assert!(!self.poisoned, "Closing killed twice");
+		self.timer.cancel(executor);
+		self.close_deadline.cancel(executor);
 		executor.remove_fd(self.fd);
 		unistd::close(self.fd).unwrap();
 		let _ = self.send.take().unwrap();
-		mem::forget(self);
+		let _ = mem::take(&mut self.config);
+		self.poisoned = true;
+	}
+	pub fn kill(mut self, executor: &impl Notifier) {
+		self.kill_in_place(executor);
+	}
+	/// The slot of the instant at which this has asked to be polled next, if any, as returned by [`Notifier::add_instant`]. It's removed on [`kill`](Closing::kill), or once obsolete.
+	///
+	/// It's stored type-erased, as this isn't generic over the notifier, so it's for the caller to downcast it to their notifier's [`InstantSlot`](Notifier::InstantSlot), rather than for this to assume it.
+	///
+	/// This doesn't include the [`ConnectionConfig::close_timeout`] deadline, if set.
+	#[must_use]
+	pub fn pending_timer(&self) -> Option<&(dyn any::Any + Send + Sync)> {
+		self.timer.slot()
+	}
+	/// Bytes in the send buffer not yet written to the socket.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Closing::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn send_buffered(&self) -> usize {
+		self.send.as_ref().unwrap().read_available()
+	}
+	/// Whether the write side has been shut down, i.e. the FIN has been handed to the kernel, which sends it after anything still in its send queue. This happens once the send buffer has been flushed.
+	#[must_use]
+	#[inline(always)]
+	pub fn local_fin_sent(&self) -> bool {
+		self.local_closed_given
+	}
+	/// The maximum segment size (`TCP_MAXSEG`) in effect, as negotiated with the remote and limited by the path MTU.
+	///
+	/// # Errors
+	///
+	/// If the socket option can't be read, or `ENOSYS` where it isn't supported.
+	pub fn mss(&self) -> io::Result<u32> {
+		mss(self.fd)
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		// once the FIN has been sent, draining the kernel send queue is polled for on a timer
+		Interest {
+			read: false,
+			write: !self.local_closed_given,
+		}
+	}
+	/// The remote address.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.remote
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		self.fd
+	}
+}
+impl StateInfo for Closing {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		self.origin
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		self.config.label.as_deref()
 	}
 }
 impl Drop for Closing {
 	fn drop(&mut self) {
+		if self.poisoned {
+			return;
+		}
 		panic!("Don't drop Closing");
 	}
 }
@@ -1033,18 +3562,249 @@ impl fmt::Debug for Closing {
 			.field("send", &self.send)
 			.field("local_closed_given", &self.local_closed_given)
 			.field("remote", &self.remote)
+			.field("config", &self.config)
+			.field("since", &self.since)
+			.field("origin", &self.origin)
+			.field("timer", &self.timer)
+			.field("close_deadline", &self.close_deadline)
+			.field("poisoned", &self.poisoned)
 			.finish()
 	}
 }
 
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+// The deadline for a graceful close, per ConnectionConfig::close_timeout, from when it's begun.
+fn close_deadline(config: &ConnectionConfig, executor: &impl Notifier) -> Timer {
+	let mut timer = Timer::new();
+	if let Some(timeout) = config.close_timeout {
+		timer.set(time::Instant::now() + timeout, executor);
+	}
+	timer
+}
+fn close_deadline_passed(close_deadline: &Timer) -> bool {
+	close_deadline
+		.instant()
+		.is_some_and(|deadline| time::Instant::now() >= deadline)
+}
+// Whether the receive buffer is full and more than RecvFull::Reset's threshold is waiting in the kernel.
+fn recv_overflowed(fd: Fd, recv: &CircularBuffer<u8>, recv_full: RecvFull) -> bool {
+	recv_full.reset_threshold().is_some_and(|threshold| {
+		recv.write_available() == 0 && palaver::socket::unreceived(fd) > threshold
+	})
+}
+
+// Zero linger, such that closing the fd aborts the connection with RST rather than continuing to flush in the background.
+pub(crate) fn set_reset_on_close(fd: Fd) {
+	let _ = socket::setsockopt(
+		fd,
+		socket::sockopt::Linger,
+		&libc::linger {
+			l_onoff: 1,
+			l_linger: 0,
+		},
+	);
+}
+
+// Matching what CircularBuffer::write_from_fd parses. On Linux and Android this is SO_TIMESTAMPING, for hardware timestamps where the NIC generates them and software ones otherwise, falling back to SO_TIMESTAMPNS where the kernel refuses it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_recv_timestamps(fd: Fd, enable: bool) -> Result<(), nix::Error> {
+	let flags = if enable {
+		libc::SOF_TIMESTAMPING_RX_HARDWARE
+			| libc::SOF_TIMESTAMPING_RAW_HARDWARE
+			| libc::SOF_TIMESTAMPING_RX_SOFTWARE
+			| libc::SOF_TIMESTAMPING_SOFTWARE
+	} else {
+		0
+	};
+	match sockopt::set::<libc::c_uint>(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, flags) {
+		Ok(()) if enable => return Ok(()),
+		Ok(()) => (),
+		Err(err) => trace!("SO_TIMESTAMPING failed, so falling back to SO_TIMESTAMPNS {err:?}"),
+	}
+	// also cleared on disable, as either may have been enabled
+	sockopt::set::<libc::c_int>(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, enable.into())
+}
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_recv_timestamps(fd: Fd, enable: bool) -> Result<(), nix::Error> {
+	socket::setsockopt(fd, socket::sockopt::ReceiveTimestamp, &enable)
+}
+
+// Best effort, as it's only a hint.
+fn set_quick_ack(fd: Fd) {
+	#[cfg(any(target_os = "linux", target_os = "android"))]
+	let _ = sockopt::set::<libc::c_int>(fd, libc::IPPROTO_TCP, libc::TCP_QUICKACK, 1);
+	#[cfg(not(any(target_os = "linux", target_os = "android")))]
+	let _ = fd;
+}
+
+// The maximum segment size currently in effect.
+fn mss(fd: Fd) -> io::Result<u32> {
+	#[cfg(any(target_os = "macos", target_os = "ios"))]
+	let mss = sockstate::maxseg(fd);
+	#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+	let mss = sockopt::get::<libc::c_int>(fd, libc::IPPROTO_TCP, libc::TCP_MAXSEG)
+		.map(|mss| mss.try_into().unwrap());
+	mss.map_err(nix_to_io)
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn delivery_stats(fd: Fd) -> Result<DeliveryStats, nix::Error> {
+	tcpinfo::delivery_stats(fd)
+}
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn delivery_stats(fd: Fd) -> Result<DeliveryStats, nix::Error> {
+	sockstate::delivery_stats(fd)
+}
+#[cfg(not(any(
+	target_os = "android",
+	target_os = "linux",
+	target_os = "ios",
+	target_os = "macos"
+)))]
+fn delivery_stats(_fd: Fd) -> Result<DeliveryStats, nix::Error> {
+	Err(nix::Error::Sys(errno::Errno::ENOSYS))
+}
+
+// Whether the remote has closed, or the error the connection has failed with. Either way the error is consumed from the socket.
+fn probe(fd: Fd, remote_closed: bool) -> Result<bool, nix::Error> {
+	match socket::getsockopt(fd, socket::sockopt::SocketError)? {
+		0 => (),
+		err => return Err(nix::Error::Sys(errno::Errno::from_i32(err))),
+	}
+	// fails with EPIPE or similar if the connection has gone, but sends nothing otherwise
+	match socket::send(fd, &[], socket::MsgFlags::empty()) {
+		Ok(_) | Err(nix::Error::Sys(errno::Errno::EAGAIN)) => (),
+		Err(err) => return Err(err),
+	}
+	Ok(remote_closed || fin_received(fd))
+}
+
+// Whether the remote's FIN has arrived, even if data received before it is yet to be read.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn fin_received(fd: Fd) -> bool {
+	tcpinfo::close_wait(fd).unwrap_or(false)
+}
+// Only once data received before the FIN has been read.
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+fn fin_received(fd: Fd) -> bool {
+	let mut buf = [0];
+	matches!(
+		socket::recv(fd, &mut buf, socket::MsgFlags::MSG_PEEK),
+		Ok(0)
+	)
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn send_queue(fd: Fd) -> Result<SendQueue, nix::Error> {
+	let mut unsent: libc::c_int = 0;
+	let res = unsafe { libc::ioctl(fd, libc::SIOCOUTQNSD, std::ptr::addr_of_mut!(unsent)) };
+	let _ = errno::Errno::result(res)?;
+	Ok(SendQueue {
+		queued: palaver::socket::unsent(fd),
+		unsent: Some(unsent.try_into().unwrap()),
+	})
+}
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn send_queue(fd: Fd) -> Result<SendQueue, nix::Error> {
+	sockstate::snd_sbbytes(fd).map(|queued| SendQueue {
+		queued,
+		unsent: None,
+	})
+}
+#[cfg(not(any(
+	target_os = "android",
+	target_os = "linux",
+	target_os = "ios",
+	target_os = "macos"
+)))]
+fn send_queue(_fd: Fd) -> Result<SendQueue, nix::Error> {
+	Err(nix::Error::Sys(errno::Errno::ENOSYS))
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn congestion_window(fd: Fd) -> Result<u32, nix::Error> {
+	tcpinfo::snd_cwnd(fd)
+}
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn congestion_window(fd: Fd) -> Result<u32, nix::Error> {
+	sockstate::snd_cwnd(fd)
+}
+#[cfg(not(any(
+	target_os = "android",
+	target_os = "linux",
+	target_os = "ios",
+	target_os = "macos"
+)))]
+fn congestion_window(_fd: Fd) -> Result<u32, nix::Error> {
+	Err(nix::Error::Sys(errno::Errno::ENOSYS))
+}
+
+// Whether a poll loop on its `count`th iteration has hit ConnectionConfig::poll_limit, and so the connection should be killed. Panics instead if so configured.
+fn poll_livelock(count: usize, remote: net::SocketAddr, config: &ConnectionConfig) -> bool {
+	if count < config.poll_limit {
+		return false;
+	}
+	let _ = POLL_LIVELOCKS.fetch_add(1, Ordering::Relaxed);
+	match config.poll_limit_action {
+		PollLimitAction::Panic => panic!("poll livelock {}", format_remote(remote, config)),
+		PollLimitAction::Kill => {
+			log::warn!("poll livelock {}", format_remote(remote, config));
+			true
+		}
+	}
+}
+
+// Send up to `len` bytes of `file` from `offset` to `fd`, returning how many were sent.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn sendfile(fd: Fd, file: Fd, mut offset: libc::off_t, len: usize) -> Result<usize, nix::Error> {
+	nix::sys::sendfile::sendfile(fd, file, Some(&mut offset), len)
+}
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn sendfile(fd: Fd, file: Fd, offset: libc::off_t, len: usize) -> Result<usize, nix::Error> {
+	let len = len.try_into().unwrap_or(libc::off_t::MAX);
+	match nix::sys::sendfile::sendfile(file, fd, offset, Some(len), None, None) {
+		// the bytes sent before the socket filled up
+		(Ok(()) | Err(nix::Error::Sys(errno::Errno::EAGAIN)), sent) => Ok(sent.try_into().unwrap()),
+		(Err(err), _) => Err(err),
+	}
+}
+#[cfg(not(any(
+	target_os = "android",
+	target_os = "linux",
+	target_os = "ios",
+	target_os = "macos"
+)))]
+fn sendfile(_fd: Fd, _file: Fd, _offset: libc::off_t, _len: usize) -> Result<usize, nix::Error> {
+	Err(nix::Error::Sys(errno::Errno::ENOSYS))
+}
+
+pub(crate) fn nix_to_io(err: nix::Error) -> io::Error {
+	match err {
+		nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+		err => io::Error::other(err),
+	}
+}
+
+// Data arriving after the remote closed is a protocol violation, or from a simultaneous close race. As we're done reading, discard it rather than leaving it to trigger a reset on close.
+fn discard_unreceived(fd: Fd) -> usize {
+	let mut discarded = 0;
+	let mut buf = [0; 4096];
+	while palaver::socket::unreceived(fd) > 0 {
+		match socket::recv(fd, &mut buf, socket::MsgFlags::empty()) {
+			Ok(0) | Err(_) => break,
+			Ok(read) => discarded += read,
+		}
+	}
+	discarded
+}
+
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 mod sockstate {
 	use nix::libc;
 	use std::convert::TryInto;
 
-	use super::Fd;
+	use super::{DeliveryStats, Fd};
 
 	#[derive(PartialEq, Eq, Debug)]
 	#[allow(non_camel_case_types)]
@@ -1082,11 +3842,57 @@ mod sockstate {
 		}
 	}
 
-	pub fn sockstate(fd: Fd) -> TcpState {
+	/// Whether the socket is still ESTABLISHED despite recv having returned EOF, rechecking briefly as the state can lag.
+	pub fn eof_while_established(fd: Fd) -> bool {
+		for _ in 0..3 {
+			if sockstate(fd).ok() != Some(TcpState::ESTABLISHED) {
+				return false;
+			}
+			std::thread::yield_now();
+		}
+		true
+	}
+
+	/// Whether bytes have been received, per `tcpi_rxbytes`, that can't be read (`FIONREAD`): the corruption that closing a socket while it's in flight to another process can cause. A heuristic, and only meaningful for a connection none of which has been read.
+	pub fn rx_unreadable(fd: Fd) -> bool {
+		// rxbytes first, as anything arriving in between only adds to what's readable
+		let rxbytes = connection_info(fd).map_or(0, |info| info.tcpi_rxbytes);
+		let readable: u64 = palaver::socket::unreceived(fd).try_into().unwrap();
+		rxbytes > readable
+	}
+
+	pub fn sockstate(fd: Fd) -> Result<TcpState, nix::Error> {
+		connection_info(fd).map(|info| TcpState::from_raw(info.tcpi_state))
+	}
+
+	/// The maximum segment size, from `tcpi_maxseg`.
+	pub fn maxseg(fd: Fd) -> Result<u32, nix::Error> {
+		connection_info(fd).map(|info| info.tcpi_maxseg)
+	}
+
+	/// Bytes in the send buffer, including those in flight, from `tcpi_snd_sbbytes`.
+	pub fn snd_sbbytes(fd: Fd) -> Result<usize, nix::Error> {
+		connection_info(fd).map(|info| info.tcpi_snd_sbbytes.try_into().unwrap())
+	}
+
+	/// The congestion window in segments, from `tcpi_snd_cwnd`, which is in bytes, and `tcpi_maxseg`.
+	pub fn snd_cwnd(fd: Fd) -> Result<u32, nix::Error> {
+		connection_info(fd).map(|info| info.tcpi_snd_cwnd / info.tcpi_maxseg.max(1))
+	}
+
+	/// The smoothed RTT and retransmissions, from `tcpi_srtt` and `tcpi_txretransmit*`.
+	pub fn delivery_stats(fd: Fd) -> Result<DeliveryStats, nix::Error> {
+		connection_info(fd).map(|info| DeliveryStats {
+			rtt: std::time::Duration::from_millis(info.tcpi_srtt.into()),
+			retransmitted_packets: info.tcpi_txretransmitpackets,
+			retransmitted_bytes: Some(info.tcpi_txretransmitbytes),
+			delivery_rate: None,
+		})
+	}
+
+	fn connection_info(fd: Fd) -> Result<tcp_connection_info, nix::Error> {
 		let mut info: tcp_connection_info = tcp_connection_info::default();
-		let mut len: libc::socklen_t = std::mem::size_of::<tcp_connection_info>()
-			.try_into()
-			.unwrap();
+		let mut len: libc::socklen_t = size_of::<tcp_connection_info>().try_into().unwrap();
 		let res = unsafe {
 			libc::getsockopt(
 				fd,
@@ -1099,9 +3905,9 @@ mod sockstate {
 				&mut len,
 			)
 		};
-		let res = nix::errno::Errno::result(res).unwrap();
+		let res = nix::errno::Errno::result(res)?;
 		assert_eq!(res, 0);
-		TcpState::from_raw(info.tcpi_state)
+		Ok(info)
 	}
 
 	// https://github.com/apple/darwin-xnu/blob/a449c6a3b8014d9406c2ddbdc81795da24aa7443/bsd/netinet/tcp.h
@@ -1136,4 +3942,152 @@ mod sockstate {
 		tcpi_rxoutoforderbytes: u64,
 		tcpi_txretransmitpackets: u64,
 	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::{eof_while_established, rx_unreadable, sockstate, TcpState};
+		use std::{
+			io::{Read, Write},
+			net,
+			os::unix::io::AsRawFd,
+		};
+
+		#[test]
+		fn loopback() {
+			let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+			let mut dialer = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+			let (mut accepted, _) = listener.accept().unwrap();
+			let fd = accepted.as_raw_fd();
+			assert_eq!(sockstate(fd).unwrap(), TcpState::ESTABLISHED);
+			assert!(!rx_unreadable(fd));
+			dialer.write_all(b"hello").unwrap();
+			let mut buf = [0; 5];
+			assert_eq!(accepted.peek(&mut buf).unwrap(), 5);
+			assert!(!rx_unreadable(fd));
+			// once read, what was received is no longer readable, which is why it's only meaningful before anything is
+			accepted.read_exact(&mut buf).unwrap();
+			assert!(rx_unreadable(fd));
+			drop(dialer);
+			assert_eq!(accepted.read(&mut buf).unwrap(), 0);
+			assert!(!eof_while_established(fd));
+			assert_ne!(sockstate(fd).unwrap(), TcpState::ESTABLISHED);
+		}
+	}
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod tcpinfo {
+	use nix::libc;
+	use std::{convert::TryInto, ptr, time};
+
+	use super::{DeliveryStats, Fd};
+
+	/// The smoothed RTT, total retransmissions and delivery rate, from `TCP_INFO`.
+	pub fn delivery_stats(fd: Fd) -> Result<DeliveryStats, nix::Error> {
+		let (info, len) = tcp_info(fd)?;
+		Ok(DeliveryStats {
+			rtt: time::Duration::from_micros(info.tcpi_rtt.into()),
+			retransmitted_packets: info.tcpi_total_retrans.into(),
+			retransmitted_bytes: None,
+			delivery_rate: if len >= size_of::<tcp_info>() {
+				Some(info.tcpi_delivery_rate)
+			} else {
+				None
+			},
+		})
+	}
+
+	/// The congestion window in segments, from `tcpi_snd_cwnd`.
+	pub fn snd_cwnd(fd: Fd) -> Result<u32, nix::Error> {
+		tcp_info(fd).map(|(info, _len)| info.tcpi_snd_cwnd)
+	}
+
+	/// For a listening socket, the number of connections waiting to be accepted, which the kernel reports as `tcpi_unacked`.
+	pub fn accept_queue(fd: Fd) -> Result<u32, nix::Error> {
+		tcp_info(fd).map(|(info, _len)| info.tcpi_unacked)
+	}
+
+	/// Whether the connection is in `TCP_CLOSE_WAIT`, i.e. the remote's FIN has been received.
+	pub fn close_wait(fd: Fd) -> Result<bool, nix::Error> {
+		const TCP_CLOSE_WAIT: u8 = 8;
+		tcp_info(fd).map(|(info, _len)| info.tcpi_state == TCP_CLOSE_WAIT)
+	}
+
+	// Along with how much of it the kernel filled in, as older kernels return a shorter struct, leaving the remainder zeroed.
+	fn tcp_info(fd: Fd) -> Result<(tcp_info, usize), nix::Error> {
+		let mut info: tcp_info = tcp_info::default();
+		let mut len: libc::socklen_t = size_of::<tcp_info>().try_into().unwrap();
+		let res = unsafe {
+			libc::getsockopt(
+				fd,
+				libc::IPPROTO_TCP,
+				libc::TCP_INFO,
+				ptr::addr_of_mut!(info).cast(),
+				ptr::addr_of_mut!(len),
+			)
+		};
+		let res = nix::errno::Errno::result(res)?;
+		assert_eq!(res, 0);
+		Ok((info, len.try_into().unwrap()))
+	}
+
+	// https://github.com/torvalds/linux/blob/v4.9/include/uapi/linux/tcp.h, up to tcpi_delivery_rate
+
+	#[derive(Copy, Clone, Default)]
+	#[repr(C)]
+	#[allow(non_camel_case_types, dead_code, clippy::struct_field_names)]
+	struct tcp_info {
+		tcpi_state: u8,
+		tcpi_ca_state: u8,
+		tcpi_retransmits: u8,
+		tcpi_probes: u8,
+		tcpi_backoff: u8,
+		tcpi_options: u8,
+		tcpi_snd_rcv_wscale: u8, /* snd_wscale : 4, rcv_wscale : 4 */
+		tcpi_app_limited: u8,    /* delivery_rate_app_limited : 1 */
+
+		tcpi_rto: u32,
+		tcpi_ato: u32,
+		tcpi_snd_mss: u32,
+		tcpi_rcv_mss: u32,
+
+		tcpi_unacked: u32,
+		tcpi_sacked: u32,
+		tcpi_lost: u32,
+		tcpi_retrans: u32,
+		tcpi_fackets: u32,
+
+		tcpi_last_data_sent: u32,
+		tcpi_last_ack_sent: u32,
+		tcpi_last_data_recv: u32,
+		tcpi_last_ack_recv: u32,
+
+		tcpi_pmtu: u32,
+		tcpi_rcv_ssthresh: u32,
+		tcpi_rtt: u32, /* smoothed RTT in us */
+		tcpi_rttvar: u32,
+		tcpi_snd_ssthresh: u32,
+		tcpi_snd_cwnd: u32,
+		tcpi_advmss: u32,
+		tcpi_reordering: u32,
+
+		tcpi_rcv_rtt: u32,
+		tcpi_rcv_space: u32,
+
+		tcpi_total_retrans: u32,
+
+		tcpi_pacing_rate: u64,
+		tcpi_max_pacing_rate: u64,
+		tcpi_bytes_acked: u64,
+		tcpi_bytes_received: u64,
+		tcpi_segs_out: u32,
+		tcpi_segs_in: u32,
+
+		tcpi_notsent_bytes: u32,
+		tcpi_min_rtt: u32,
+		tcpi_data_segs_in: u32,
+		tcpi_data_segs_out: u32,
+
+		tcpi_delivery_rate: u64, /* bytes per second */
+	}
 }
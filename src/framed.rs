@@ -0,0 +1,163 @@
+//! Length-prefixed framing over a [`Connection`]. Only with the `framed` feature.
+
+use super::*;
+use log::trace;
+use std::{
+	cmp,
+	collections::VecDeque,
+	convert::{TryFrom, TryInto},
+};
+
+const PREFIX: usize = 4;
+
+/// A [`Connection`] carrying frames: each is sent as its length, as a big-endian `u32`, followed by that many bytes.
+///
+/// It's made from a [`Connected`], and follows the connection through its states thereafter: frames can be sent while it's [`sendable`](Connection::sendable), and received while it's [`recvable`](Connection::recvable). A received frame longer than the maximum kills the connection, as the stream can't be resynchronised.
+///
+/// Frames that don't yet fit in the connection's send buffer are queued, up to a limit, beyond which [`send_frame`](FrameConnection::send_frame) refuses them until the remote catches up.
+///
+/// Like [`Pump`], this should be polled as instructed by the [`Notifier`], after which [`poll_frame`](FrameConnection::poll_frame) should be called until it returns `None`.
+#[derive(Debug)]
+pub struct FrameConnection {
+	connection: Connection,
+	max_frame: usize,
+	max_queued: usize,
+	// frames yet to fit in the connection's send buffer
+	send: VecDeque<u8>,
+	// the frame being received, prefix included
+	recv: Vec<u8>,
+	closing: bool,
+}
+impl FrameConnection {
+	/// Frames longer than `max_frame` bytes are neither sent nor received. At most `max_queued` bytes of frames, length prefixes included, are queued beyond the connection's send buffer; it must fit at least one frame of `max_frame` bytes.
+	///
+	/// # Panics
+	///
+	/// If `max_frame` doesn't fit in a `u32`, or `max_queued` is less than a frame of `max_frame` bytes and its prefix.
+	#[must_use]
+	pub fn new(connected: Connected, max_frame: usize, max_queued: usize) -> Self {
+		assert!(u32::try_from(max_frame).is_ok(), "max_frame too large");
+		assert!(max_queued >= PREFIX + max_frame, "max_queued too small");
+		Self {
+			connection: Connection::Connected(connected),
+			max_frame,
+			max_queued,
+			send: VecDeque::new(),
+			recv: Vec::new(),
+			closing: false,
+		}
+	}
+	pub fn poll(&mut self, executor: &impl Notifier) {
+		if !self.connection.is_terminal() {
+			self.connection.poll(executor);
+		}
+		self.flush(executor);
+	}
+	/// Whether a frame of the maximum length would be accepted by [`send_frame`](FrameConnection::send_frame), i.e. the connection can send, isn't closing, and the queue has room for it.
+	#[must_use]
+	pub fn sendable(&self) -> bool {
+		!self.closing
+			&& self.connection.sendable()
+			&& self.send.len() + PREFIX + self.max_frame <= self.max_queued
+	}
+	/// Queue `frame` to be sent. Returns `false`, and sends nothing, if the connection can no longer send or is closing, or if the queue is too full to take it, in which case it can be retried once polling has drained the queue.
+	///
+	/// # Panics
+	///
+	/// If `frame` is longer than the maximum.
+	pub fn send_frame(&mut self, frame: &[u8], executor: &impl Notifier) -> bool {
+		assert!(frame.len() <= self.max_frame, "frame too long");
+		if self.closing
+			|| !self.connection.sendable()
+			|| self.send.len() + PREFIX + frame.len() > self.max_queued
+		{
+			return false;
+		}
+		let len: u32 = frame.len().try_into().unwrap();
+		self.send.extend(&len.to_be_bytes());
+		self.send.extend(frame);
+		self.flush(executor);
+		true
+	}
+	/// The next received frame, if one has arrived in full.
+	pub fn poll_frame(&mut self, executor: &impl Notifier) -> Option<Vec<u8>> {
+		loop {
+			let mut want = PREFIX;
+			if self.recv.len() >= PREFIX {
+				let len =
+					u32::from_be_bytes([self.recv[0], self.recv[1], self.recv[2], self.recv[3]])
+						as usize;
+				if len > self.max_frame {
+					trace!(
+						"FrameConnection frame too long {:?}",
+						self.connection.remote()
+					);
+					self.recv.clear();
+					self.kill(executor);
+					return None;
+				}
+				want += len;
+				if self.recv.len() == want {
+					let frame = self.recv.split_off(PREFIX);
+					self.recv.clear();
+					return Some(frame);
+				}
+			}
+			let have = self.recv.len();
+			let n = cmp::min(want - have, self.connection.recv_avail()?);
+			if n == 0 {
+				return None;
+			}
+			self.recv.resize(have + n, 0);
+			let read = self.connection.recv_slice(&mut self.recv[have..], executor);
+			debug_assert_eq!(read, n);
+		}
+	}
+	/// Bytes of frames queued but yet to fit in the connection's send buffer.
+	#[must_use]
+	#[inline(always)]
+	pub fn send_queued(&self) -> usize {
+		self.send.len()
+	}
+	/// Close the connection once all queued frames have been handed to it.
+	pub fn close(&mut self, executor: &impl Notifier) {
+		self.closing = true;
+		self.flush(executor);
+	}
+	pub fn kill(&mut self, executor: &impl Notifier) {
+		self.send.clear();
+		if let Some(kill) = self.connection.kill(executor) {
+			kill();
+		}
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn connection(&self) -> &Connection {
+		&self.connection
+	}
+	/// The connection, along with any partially received frame, prefix included.
+	#[must_use]
+	pub fn into_inner(self) -> (Connection, Vec<u8>) {
+		(self.connection, self.recv)
+	}
+
+	fn flush(&mut self, executor: &impl Notifier) {
+		while !self.send.is_empty() {
+			let sent = self
+				.connection
+				.send_slice(self.send.as_slices().0, executor);
+			if sent == 0 {
+				break;
+			}
+			drop(self.send.drain(..sent));
+		}
+		if !self.connection.sendable() {
+			self.send.clear();
+		}
+		if self.closing && self.send.is_empty() {
+			if let Some(close) = self.connection.close(executor) {
+				close();
+			}
+		}
+	}
+}
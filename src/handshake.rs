@@ -0,0 +1,255 @@
+//! Running a protocol handshake, such as version negotiation, over a newly established connection before it's handed over for use.
+
+use super::*;
+use circular_buffer::CircularBuffer;
+use log::trace;
+use std::{fmt, io};
+
+/// What a handshake returns each time it's run.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HandshakeProgress {
+	/// Waiting on more data from the remote, or on space to send; run again on the next poll that makes progress.
+	Pending,
+	/// The handshake succeeded; anything it didn't read remains in the receive buffer.
+	Done,
+	/// The handshake failed, and the connection is killed.
+	Failed,
+}
+
+/// A handshake, as given to [`Connectee::handshake`] or [`Connected::handshake`].
+pub type Handshake = Box<dyn FnMut(&mut HandshakeStream<'_>) -> HandshakeProgress + Send + Sync>;
+
+/// The connection as seen by a handshake. Reads take from the receive buffer and writes add to the send buffer, returning [`WouldBlock`](io::ErrorKind::WouldBlock) when empty or full respectively.
+pub struct HandshakeStream<'a> {
+	recv: &'a mut CircularBuffer<u8>,
+	send: &'a mut CircularBuffer<u8>,
+	moved: bool,
+}
+impl HandshakeStream<'_> {
+	#[must_use]
+	#[inline(always)]
+	pub fn recv_avail(&self) -> usize {
+		self.recv.read_available()
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn send_avail(&self) -> usize {
+		self.send.write_available()
+	}
+}
+impl io::Read for HandshakeStream<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.recv.read_available() == 0 && !buf.is_empty() {
+			return Err(io::ErrorKind::WouldBlock.into());
+		}
+		let read = self.recv.read_slice(buf);
+		self.moved |= read > 0;
+		Ok(read)
+	}
+}
+impl io::Write for HandshakeStream<'_> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = self.send.write_slice(buf);
+		if written == 0 && !buf.is_empty() {
+			return Err(io::ErrorKind::WouldBlock.into());
+		}
+		self.moved |= written > 0;
+		Ok(written)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+impl fmt::Debug for HandshakeStream<'_> {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("HandshakeStream")
+			.field("recv", &self.recv)
+			.field("send", &self.send)
+			.field("moved", &self.moved)
+			.finish()
+	}
+}
+
+#[derive(Debug)]
+enum Inner {
+	Connectee(Connectee),
+	Connected(Connected),
+}
+
+#[derive(Debug)]
+pub enum HandshakingPoll {
+	Handshaking(Handshaking),
+	Connected(Connected),
+	RemoteClosed(RemoteClosed),
+	Reset(Reset),
+	Killed,
+}
+/// An accepted or connected connection running a handshake, after which it becomes a [`Connected`].
+///
+/// If the handshake fails it is killed. If the remote closes or resets before it's done, it becomes a [`RemoteClosed`] or [`Reset`], the latter holding anything received that the handshake didn't read.
+pub struct Handshaking {
+	// only None transiently, during poll
+	inner: Option<Inner>,
+	handshake: Handshake,
+	since: time::Instant,
+}
+impl Handshaking {
+	fn new(inner: Inner, handshake: Handshake, executor: &impl Notifier) -> HandshakingPoll {
+		Self {
+			inner: Some(inner),
+			handshake,
+			since: time::Instant::now(),
+		}
+		.poll(executor)
+	}
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Handshaking::kill_in_place).
+	pub fn poll(mut self, executor: &impl Notifier) -> HandshakingPoll {
+		let poll = match self.inner.take().unwrap() {
+			Inner::Connectee(connectee) => match connectee.poll(executor) {
+				ConnecteePoll::Connectee(x) => {
+					self.inner = Some(Inner::Connectee(x));
+					return HandshakingPoll::Handshaking(self);
+				}
+				ConnecteePoll::Connected(x) => ConnectedPoll::Connected(x),
+				ConnecteePoll::RemoteClosed(x) => ConnectedPoll::RemoteClosed(x),
+				ConnecteePoll::Killed => ConnectedPoll::Killed,
+			},
+			Inner::Connected(connected) => connected.poll(executor),
+		};
+		let mut connected = match poll {
+			ConnectedPoll::Connected(connected) => connected,
+			ConnectedPoll::RemoteClosed(remote_closed) => {
+				trace!("Handshaking remote closed {}", remote_closed.remote());
+				return HandshakingPoll::RemoteClosed(remote_closed);
+			}
+			ConnectedPoll::Reset(reset) => {
+				trace!("Handshaking reset {}", reset.remote());
+				return HandshakingPoll::Reset(reset);
+			}
+			ConnectedPoll::Killed => return HandshakingPoll::Killed,
+		};
+		let (recv, send) = connected.buffers();
+		let mut stream = HandshakeStream {
+			recv,
+			send,
+			moved: false,
+		};
+		let progress = (self.handshake)(&mut stream);
+		if stream.moved {
+			// so that what was sent is flushed, and space freed in the receive buffer is refilled
+			executor.queue();
+		}
+		match progress {
+			HandshakeProgress::Pending => {
+				self.inner = Some(Inner::Connected(connected));
+				HandshakingPoll::Handshaking(self)
+			}
+			HandshakeProgress::Done => {
+				trace!("Handshaking done {}", connected.remote());
+				connected.entered_state();
+				HandshakingPoll::Connected(connected)
+			}
+			HandshakeProgress::Failed => {
+				trace!("Handshaking failed {}", connected.remote());
+				connected.kill(executor);
+				HandshakingPoll::Killed
+			}
+		}
+	}
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Handshaking::kill_in_place).
+	pub fn kill(self, executor: &impl Notifier) {
+		match self.inner.unwrap() {
+			Inner::Connectee(connectee) => connectee.kill(executor),
+			Inner::Connected(connected) => connected.kill(executor),
+		}
+	}
+	/// Like [`kill`](Handshaking::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		match self.inner.take().expect("Handshaking killed twice") {
+			Inner::Connectee(connectee) => connectee.kill(executor),
+			Inner::Connected(connected) => connected.kill(executor),
+		}
+	}
+	/// The readiness this is waiting on, as of the last poll.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Handshaking::kill_in_place).
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		match self.inner.as_ref().unwrap() {
+			Inner::Connectee(connectee) => connectee.interest(),
+			Inner::Connected(connected) => connected.interest(),
+		}
+	}
+	/// The remote address.
+	///
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Handshaking::kill_in_place).
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		match self.inner.as_ref().unwrap() {
+			Inner::Connectee(connectee) => connectee.remote(),
+			Inner::Connected(connected) => connected.remote(),
+		}
+	}
+	// The socket.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Fd {
+		match self.inner.as_ref().unwrap() {
+			Inner::Connectee(connectee) => connectee.fd(),
+			Inner::Connected(connected) => connected.fd(),
+		}
+	}
+}
+impl StateInfo for Handshaking {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		match self.inner.as_ref().unwrap() {
+			Inner::Connectee(connectee) => connectee.origin(),
+			Inner::Connected(connected) => connected.origin(),
+		}
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		match self.inner.as_ref().unwrap() {
+			Inner::Connectee(connectee) => connectee.label(),
+			Inner::Connected(connected) => connected.label(),
+		}
+	}
+}
+impl fmt::Debug for Handshaking {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("Handshaking")
+			.field("inner", &self.inner)
+			.field("since", &self.since)
+			.finish_non_exhaustive()
+	}
+}
+
+impl Connectee {
+	/// Run `handshake` once accepted, before becoming [`Connected`].
+	pub fn handshake(self, handshake: Handshake, executor: &impl Notifier) -> HandshakingPoll {
+		Handshaking::new(Inner::Connectee(self), handshake, executor)
+	}
+}
+impl Connected {
+	/// Run `handshake` before being handed back as [`Connected`].
+	pub fn handshake(self, handshake: Handshake, executor: &impl Notifier) -> HandshakingPoll {
+		Handshaking::new(Inner::Connected(self), handshake, executor)
+	}
+}
@@ -32,49 +32,280 @@
 	clippy::if_not_else,
 	clippy::indexing_slicing,
 	clippy::new_ret_no_self,
-	clippy::needless_pass_by_value
+	clippy::needless_pass_by_value,
+	clippy::too_many_arguments,
+	clippy::large_enum_variant
 )]
 
+#[cfg(feature = "async")]
+mod async_connect;
+mod blocking;
 mod circular_buffer;
+mod config;
 mod connection;
 mod connection_states;
+#[cfg(feature = "framed")]
+mod framed;
+mod handshake;
+mod proxy_protocol;
+mod pump;
+mod shutdown;
 mod socket_forwarder;
+mod sockopt;
+mod socks5;
+#[cfg(feature = "rustls")]
+mod tls;
+#[cfg(feature = "testing")]
+mod transitions;
 
-use std::{fmt, net, time};
+use std::{
+	any, fmt, net,
+	sync::atomic::{AtomicUsize, Ordering},
+	time,
+};
 
 #[cfg(unix)]
 type Fd = std::os::unix::io::RawFd;
 #[cfg(windows)]
 type Fd = std::os::windows::io::RawHandle;
 
+#[cfg(feature = "async")]
+pub use async_connect::*;
+pub use blocking::*;
+pub use config::*;
 pub use connection::*;
 pub use connection_states::*;
+#[cfg(feature = "framed")]
+pub use framed::*;
+pub use handshake::*;
+pub use pump::*;
+pub use shutdown::*;
 pub use socket_forwarder::*;
+pub use socks5::*;
+#[cfg(feature = "rustls")]
+pub use tls::*;
+#[cfg(feature = "testing")]
+pub use transitions::*;
 
 /// Implementers and users are responsible for calling `fn poll(self, &impl Notifier)` on [Connection]s or the states ([Connecter], [Connectee], [ConnecterLocalClosed], etc) as instructed by calls made to it via this trait.
 pub trait Notifier {
-	type InstantSlot;
+	/// Stored (as a [`Timer`]) by the states, so they can cancel instants that are obsolete.
+	///
+	/// It's stored type-erased, so that the states needn't be generic over the notifier, and must hence be `'static`; and `Send + Sync`, so that the states remain so. Notifiers whose slots aren't, such as ones holding an `Rc`, need to return a handle to them instead, such as an index or key.
+	type InstantSlot: Send + Sync + 'static;
 	/// Poll as soon as possible; equivalent to add_instant(Instant::now()).
 	fn queue(&self);
 	/// Poll when we receive an edge-triggered event on this file descriptor.
 	fn add_fd(&self, fd: Fd);
 	/// No longer poll when we receive events on this file descriptor.
 	fn remove_fd(&self, fd: Fd);
-	/// Poll at this (typically future) instant.
+	/// Poll on events on `new` instead of `old`, as when a connect is retried on a fresh socket. `old` is closed straight after. Defaults to [`remove_fd`](Notifier::remove_fd) then [`add_fd`](Notifier::add_fd); notifiers for which registration is costly can override it.
+	fn replace_fd(&self, old: Fd, new: Fd) {
+		self.remove_fd(old);
+		self.add_fd(new);
+	}
+	/// Poll at this (typically future) instant. An instant that has already passed, as it may have by the time it's added, must be polled as soon as possible, as with [`queue`](Notifier::queue).
 	fn add_instant(&self, instant: time::Instant) -> Self::InstantSlot;
-	/// No longer poll at this specific previously added instant.
+	/// No longer poll at this specific previously added instant. This is called for instants that are obsolete but yet to pass.
 	fn remove_instant(&self, slot: Self::InstantSlot);
 }
 
-fn format_remote(addr: net::SocketAddr) -> RemoteAddr {
-	RemoteAddr(addr)
+/// The readiness a connection is waiting on, for [`Notifier`]s that would rather register fds for only what's needed than for both.
+///
+/// Edge-triggered notifications on an fd registered for both remain sufficient; this is an optimisation.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Interest {
+	pub read: bool,
+	pub write: bool,
+}
+
+/// Whether a connection was dialed, by [`Connecter`] or [`Socks5`], or accepted, by [`Listener`], for protocols where the two ends have different roles.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Origin {
+	Dialer,
+	Listener,
+}
+
+/// Accessors common to each of the states a connection passes through, such as [`Connected`] and [`Closing`]. [`Connection`] has equivalents that return `None` once it's closed or killed.
+pub trait StateInfo {
+	/// How long this has been in its current state.
+	fn time_in_state(&self) -> time::Duration;
+	/// Whether this connection was dialed or accepted.
+	fn origin(&self) -> Origin;
+	/// The label given by [`ConnectionConfig::label`], if any.
+	fn label(&self) -> Option<&str>;
+}
+
+/// Loss and throughput statistics for a connection, as returned by [`Connected::delivery_stats`], for adapting the rate sent at to the path. Taken from `TCP_INFO` on Linux and Android, and `TCP_CONNECTION_INFO` on macOS and iOS.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DeliveryStats {
+	/// The smoothed round-trip time.
+	pub rtt: time::Duration,
+	/// How many segments have been retransmitted over the connection's lifetime.
+	pub retransmitted_packets: u64,
+	/// How many bytes have been retransmitted over the connection's lifetime. Only on macOS and iOS.
+	pub retransmitted_bytes: Option<u64>,
+	/// The most recent estimate of the rate data is being delivered to the remote at, in bytes per second. Only on Linux 4.9 and later, and Android.
+	pub delivery_rate: Option<u64>,
+}
+
+/// What's in the kernel's send buffer for a connection, as returned by [`Connected::send_queue`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SendQueue {
+	/// Bytes in the send buffer, both those yet to be sent and those sent but not yet acknowledged.
+	pub queued: usize,
+	/// Of those, bytes yet to be sent. Only on Linux and Android.
+	pub unsent: Option<usize>,
+}
+impl SendQueue {
+	/// Bytes sent but not yet acknowledged, where known.
+	#[must_use]
+	pub fn in_flight(&self) -> Option<usize> {
+		self.unsent.map(|unsent| self.queued - unsent)
+	}
+}
+
+/// The state of a connection's send and receive buffers, as returned by [`Connected::buffer_debug`]. Only with the `diagnostics` feature.
+#[cfg(feature = "diagnostics")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BufferDebug {
+	pub send: RingDebug,
+	pub recv: RingDebug,
+}
+/// One of the ring buffers in a [`BufferDebug`].
+#[cfg(feature = "diagnostics")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RingDebug {
+	pub capacity: usize,
+	/// The offset of the next byte to be read out.
+	pub read_pos: usize,
+	/// The offset the next byte will be written at.
+	pub write_pos: usize,
+	/// How many bytes are buffered.
+	pub len: usize,
+	/// Whether the buffered bytes wrap around the end of the ring, so that they're read out, or written to the socket, in two segments rather than one.
+	pub wrapped: bool,
+}
+
+/// A pending [`Notifier::add_instant`], such that it can be cancelled with [`Notifier::remove_instant`] once obsolete.
+///
+/// The slot is type-erased, so holders needn't be generic over the [`Notifier`]. It must be cancelled with the same [`Notifier`] it was set with.
+#[derive(Default)]
+pub struct Timer(Option<(time::Instant, Box<dyn any::Any + Send + Sync>)>);
+impl Timer {
+	#[must_use]
+	pub fn new() -> Self {
+		Self(None)
+	}
+	/// Poll at `instant`, cancelling any previously set instant. Setting the instant already set is a no-op.
+	pub fn set<N: Notifier>(&mut self, instant: time::Instant, executor: &N) {
+		if self.instant() == Some(instant) {
+			return;
+		}
+		self.cancel(executor);
+		self.0 = Some((instant, Box::new(executor.add_instant(instant))));
+		// in case the notifier doesn't fire instants that were already past when added
+		if instant <= time::Instant::now() {
+			executor.queue();
+		}
+	}
+	/// Like [`set`](Timer::set), but leaving in place a set instant that's yet to pass, for re-polling periodically without re-adding an instant on every poll.
+	pub fn set_unless_pending<N: Notifier>(&mut self, instant: time::Instant, executor: &N) {
+		if self
+			.instant()
+			.is_none_or(|pending| pending <= time::Instant::now())
+		{
+			self.set(instant, executor);
+		}
+	}
+	/// Cancel the pending instant, if any. Instants that have already passed are assumed to have fired, and aren't removed.
+	pub fn cancel<N: Notifier>(&mut self, executor: &N) {
+		if let Some((instant, slot)) = self.0.take() {
+			if time::Instant::now() < instant {
+				debug_assert!(
+					slot.is::<N::InstantSlot>(),
+					"Timer cancelled with a different Notifier to the one it was set with"
+				);
+				if let Ok(slot) = slot.downcast() {
+					executor.remove_instant(*slot);
+				}
+			}
+		}
+	}
+	#[must_use]
+	pub fn instant(&self) -> Option<time::Instant> {
+		self.0.as_ref().map(|&(instant, _)| instant)
+	}
+	/// The slot returned by [`Notifier::add_instant`] for the pending instant, if any, as stored: to be downcast to the notifier's [`InstantSlot`](Notifier::InstantSlot).
+	#[must_use]
+	pub fn slot(&self) -> Option<&(dyn any::Any + Send + Sync)> {
+		self.0.as_ref().map(|(_, slot)| &**slot)
+	}
+}
+impl fmt::Debug for Timer {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_tuple("Timer").field(&self.instant()).finish()
+	}
+}
+
+fn address_family(addr: &net::SocketAddr) -> nix::sys::socket::AddressFamily {
+	match addr {
+		net::SocketAddr::V4(_) => nix::sys::socket::AddressFamily::Inet,
+		net::SocketAddr::V6(_) => nix::sys::socket::AddressFamily::Inet6,
+	}
+}
+
+/// Report IPv4 peers of dualstack sockets as native IPv4 rather than IPv4-mapped IPv6.
+fn unmap_remote(addr: net::SocketAddr) -> net::SocketAddr {
+	match addr {
+		net::SocketAddr::V6(addr) => addr
+			.ip()
+			.to_ipv4_mapped()
+			.map_or(net::SocketAddr::V6(addr), |ip| {
+				net::SocketAddr::new(ip.into(), addr.port())
+			}),
+		addr @ net::SocketAddr::V4(_) => addr,
+	}
+}
+
+fn format_remote(addr: net::SocketAddr, config: &ConnectionConfig) -> RemoteAddr<'_> {
+	RemoteAddr(addr, config.label.as_deref())
 }
-struct RemoteAddr(net::SocketAddr);
-impl fmt::Display for RemoteAddr {
+struct RemoteAddr<'a>(net::SocketAddr, Option<&'a str>);
+impl fmt::Display for RemoteAddr<'_> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "{}", &self.0)
+		if let Some(label) = self.1 {
+			write!(f, "{} ({})", label, &self.0)
+		} else {
+			write!(f, "{}", &self.0)
+		}
 	}
 }
 
+/// The default [`ConnectionConfig::poll_limit`].
+pub const POLL_LIMIT: usize = 1_000;
+
+static POLL_LIVELOCKS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of times any connection in this process has hit its [`ConnectionConfig::poll_limit`].
+pub fn poll_livelocks() -> usize {
+	POLL_LIVELOCKS.load(Ordering::Relaxed)
+}
+
+static TERMINAL_POLLS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of times [`Connection::poll`] has been called in this process on a connection already [`Closed`](Connection::Closed) or [`Killed`](Connection::Killed), which is likely a bug in the caller.
+pub fn terminal_polls() -> usize {
+	TERMINAL_POLLS.load(Ordering::Relaxed)
+}
+
 const BUF: usize = 64 * 1024;
+const IDLE_BUF: usize = 4 * 1024;
 const LISTEN_BACKLOG: usize = 128;
+const CONNECT_IMMEDIATE_RETRIES: usize = 10;
+const BIND_BACKOFF_MIN: time::Duration = time::Duration::from_millis(1);
+const BIND_BACKOFF_MAX: time::Duration = time::Duration::from_secs(1);
+const TRANSIENT_RETRIES: u32 = 5;
+const TRANSIENT_BACKOFF: time::Duration = time::Duration::from_millis(10);
+const FLUSH_BACKOFF_MIN: time::Duration = time::Duration::from_millis(1);
+const FLUSH_BACKOFF_MAX: time::Duration = time::Duration::from_millis(100);
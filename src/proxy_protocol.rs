@@ -0,0 +1,221 @@
+//! Parsing the [PROXY protocol](https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt) header sent by load balancers ahead of the proxied byte stream.
+
+use std::{convert::TryInto, net, str};
+
+const V1_SIGNATURE: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+/// How much to peek in one go; enough for any v1 header, and v2 headers without large TLVs.
+pub(crate) const PEEK_LEN: usize = 232;
+
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum Header {
+	/// Needs at least this many bytes.
+	Incomplete(usize),
+	/// The header is this many bytes long, and carries the source address unless it's a health check or of an unsupported family.
+	Complete(usize, Option<net::SocketAddr>),
+	Invalid,
+}
+
+pub(crate) fn parse(buf: &[u8]) -> Header {
+	if buf.starts_with(V1_SIGNATURE) {
+		parse_v1(buf)
+	} else if buf.starts_with(V2_SIGNATURE) {
+		parse_v2(buf)
+	} else if V1_SIGNATURE.starts_with(buf) || V2_SIGNATURE.starts_with(buf) {
+		Header::Incomplete(buf.len() + 1)
+	} else {
+		Header::Invalid
+	}
+}
+
+fn parse_v1(buf: &[u8]) -> Header {
+	let end = match buf[..buf.len().min(V1_MAX_LEN)]
+		.windows(2)
+		.position(|x| x == b"\r\n")
+	{
+		Some(end) => end,
+		None if buf.len() < V1_MAX_LEN => return Header::Incomplete(buf.len() + 1),
+		None => return Header::Invalid,
+	};
+	let Ok(line) = str::from_utf8(&buf[V1_SIGNATURE.len()..end]) else {
+		return Header::Invalid;
+	};
+	let mut fields = line.split(' ');
+	let addr = match fields.next() {
+		Some("UNKNOWN") => None,
+		Some("TCP4" | "TCP6") => {
+			let src = fields.next().and_then(|x| x.parse::<net::IpAddr>().ok());
+			let _dst = fields.next();
+			let port = fields.next().and_then(|x| x.parse::<u16>().ok());
+			if let (Some(src), Some(port)) = (src, port) {
+				Some(net::SocketAddr::new(src, port))
+			} else {
+				return Header::Invalid;
+			}
+		}
+		_ => return Header::Invalid,
+	};
+	Header::Complete(end + 2, addr)
+}
+
+fn parse_v2(buf: &[u8]) -> Header {
+	if buf.len() < V2_HEADER_LEN {
+		return Header::Incomplete(V2_HEADER_LEN);
+	}
+	let (version_command, family) = (buf[12], buf[13]);
+	let len = V2_HEADER_LEN + usize::from(u16::from_be_bytes(buf[14..16].try_into().unwrap()));
+	if version_command >> 4 != 2 || version_command & 0xf > 1 {
+		return Header::Invalid;
+	}
+	if buf.len() < len {
+		return Header::Incomplete(len);
+	}
+	let body = &buf[V2_HEADER_LEN..len];
+	// LOCAL connections (health checks from the proxy itself) keep the real peer address
+	let addr = match (version_command & 0xf, family) {
+		(1, 0x11) if body.len() >= 12 => {
+			let ip: [u8; 4] = body[..4].try_into().unwrap();
+			let port = u16::from_be_bytes(body[8..10].try_into().unwrap());
+			Some(net::SocketAddr::new(ip.into(), port))
+		}
+		(1, 0x21) if body.len() >= 36 => {
+			let ip: [u8; 16] = body[..16].try_into().unwrap();
+			let port = u16::from_be_bytes(body[32..34].try_into().unwrap());
+			Some(net::SocketAddr::new(ip.into(), port))
+		}
+		_ => None,
+	};
+	Header::Complete(len, addr)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse, Header, V2_SIGNATURE};
+	use std::{convert::TryFrom, net};
+
+	fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+		let len = u16::try_from(body.len()).unwrap().to_be_bytes();
+		[V2_SIGNATURE, &[0x20 | command, family], &len, body].concat()
+	}
+
+	#[test]
+	fn v1() {
+		let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+		assert_eq!(
+			parse(header),
+			Header::Complete(
+				header.len() - 5,
+				Some(net::SocketAddr::from(([192, 0, 2, 1], 56324)))
+			)
+		);
+		let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+		assert_eq!(
+			parse(header),
+			Header::Complete(header.len(), Some("[2001:db8::1]:56324".parse().unwrap()))
+		);
+		let header = b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n";
+		assert_eq!(parse(header), Header::Complete(header.len(), None));
+		assert_eq!(parse(b"PROXY UNKNOWN\r\n"), Header::Complete(15, None));
+	}
+
+	#[test]
+	fn v2_local() {
+		let header = v2(0, 0, &[]);
+		assert_eq!(parse(&header), Header::Complete(16, None));
+		// LOCAL ignores any addresses
+		let header = v2(
+			0,
+			0x11,
+			&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb],
+		);
+		assert_eq!(parse(&header), Header::Complete(28, None));
+	}
+
+	#[test]
+	fn v2_proxy() {
+		let header = v2(
+			1,
+			0x11,
+			&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb],
+		);
+		assert_eq!(
+			parse(&header),
+			Header::Complete(28, Some(net::SocketAddr::from(([192, 0, 2, 1], 56324))))
+		);
+		let mut body = vec![0; 36];
+		body[0] = 0x20;
+		body[1] = 0x01;
+		body[15] = 1;
+		body[32..34].copy_from_slice(&56324_u16.to_be_bytes());
+		let header = v2(1, 0x21, &body);
+		assert_eq!(
+			parse(&header),
+			Header::Complete(52, Some("[2001::1]:56324".parse().unwrap()))
+		);
+		// TLVs after the addresses are skipped
+		let mut body = vec![192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb];
+		body.extend(&[0x04, 0, 1, 0]);
+		let header = v2(1, 0x11, &body);
+		assert_eq!(
+			parse(&header),
+			Header::Complete(32, Some(net::SocketAddr::from(([192, 0, 2, 1], 56324))))
+		);
+		// unsupported families still carry a length to consume
+		let header = v2(1, 0x31, &[0; 216]);
+		assert_eq!(parse(&header), Header::Complete(232, None));
+	}
+
+	#[test]
+	fn incomplete() {
+		assert_eq!(parse(b""), Header::Incomplete(1));
+		assert_eq!(parse(b"PRO"), Header::Incomplete(4));
+		assert_eq!(parse(b"PROXY TCP4 192.0.2.1"), Header::Incomplete(21));
+		assert_eq!(
+			parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r"),
+			Header::Incomplete(45)
+		);
+		assert_eq!(parse(&V2_SIGNATURE[..5]), Header::Incomplete(6));
+		let header = v2(
+			1,
+			0x11,
+			&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 1, 0xbb],
+		);
+		assert_eq!(parse(&header[..14]), Header::Incomplete(16));
+		assert_eq!(parse(&header[..20]), Header::Incomplete(28));
+	}
+
+	#[test]
+	fn malformed() {
+		assert_eq!(parse(b"GET / HTTP/1.1\r\n"), Header::Invalid);
+		assert_eq!(parse(b"PROXY\r\n"), Header::Invalid);
+		assert_eq!(
+			parse(b"PROXY TCP5 192.0.2.1 198.51.100.1 56324 443\r\n"),
+			Header::Invalid
+		);
+		assert_eq!(
+			parse(b"PROXY TCP4 192.0.2.x 198.51.100.1 56324 443\r\n"),
+			Header::Invalid
+		);
+		assert_eq!(
+			parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 443\r\n"),
+			Header::Invalid
+		);
+		assert_eq!(parse(&[b'P'; 200]), Header::Invalid);
+		// no CRLF within the maximum length
+		let mut header = b"PROXY UNKNOWN ".to_vec();
+		header.resize(200, b'f');
+		assert_eq!(parse(&header), Header::Invalid);
+		let mut signature = V2_SIGNATURE.to_vec();
+		signature[11] = b'X';
+		assert_eq!(parse(&signature), Header::Invalid);
+		// version 1 and unknown commands
+		let mut header = v2(1, 0x11, &[0; 12]);
+		header[12] = 0x11;
+		assert_eq!(parse(&header), Header::Invalid);
+		header[12] = 0x22;
+		assert_eq!(parse(&header), Header::Invalid);
+	}
+}
@@ -0,0 +1,86 @@
+use super::*;
+use std::cmp;
+
+/// Relay bytes in both directions between two [`Connection`]s, as for a proxy.
+///
+/// Each direction is subject to backpressure: no more is received from one side than there's space to send to the other. Half-closes are propagated: once one side's remote has closed and everything received from it has been relayed, the other side is [`close`](Connection::close)d, which shuts down its write once its send buffer is flushed, and anything then received by it is discarded once the first side has closed, as there's nowhere left to relay it. If one side is killed, including a reset one once what it received has been relayed, the other is [`reset`](Connection::reset), so that the abort reaches its remote too.
+///
+/// Either side may still be connecting. Like the connections themselves, this should be polled as instructed by the [`Notifier`] until it returns `true`.
+#[derive(Debug)]
+pub struct Pump {
+	a: Connection,
+	b: Connection,
+}
+impl Pump {
+	pub fn new(a: Connection, b: Connection, executor: &impl Notifier) -> Self {
+		let mut pump = Self { a, b };
+		let _ = pump.poll(executor);
+		pump
+	}
+	/// Returns `true` once both connections have reached [`Connection::Closed`] or [`Connection::Killed`], after which this needn't be polled again.
+	pub fn poll(&mut self, executor: &impl Notifier) -> bool {
+		for connection in [&mut self.a, &mut self.b] {
+			if !connection.is_terminal() {
+				connection.poll(executor);
+			}
+		}
+		relay(&mut self.a, &mut self.b, executor);
+		relay(&mut self.b, &mut self.a, executor);
+		self.is_done()
+	}
+	/// Whether both connections have reached [`Connection::Closed`] or [`Connection::Killed`]. Both are `Closed` if everything was relayed and closed cleanly.
+	#[must_use]
+	#[inline(always)]
+	pub fn is_done(&self) -> bool {
+		self.a.is_terminal() && self.b.is_terminal()
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn a(&self) -> &Connection {
+		&self.a
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn b(&self) -> &Connection {
+		&self.b
+	}
+	/// Kill both connections.
+	pub fn kill(&mut self, executor: &impl Notifier) {
+		for connection in [&mut self.a, &mut self.b] {
+			if let Some(kill) = connection.kill(executor) {
+				kill();
+			}
+		}
+	}
+	#[must_use]
+	pub fn into_inner(self) -> (Connection, Connection) {
+		(self.a, self.b)
+	}
+}
+
+// Relay what's been received from `from` to `to`, and close `to` once `from` has no more to give, or reset it if `from` was killed.
+fn relay(from: &mut Connection, to: &mut Connection, executor: &impl Notifier) {
+	let mut buf = [0; 4096];
+	let mut n = cmp::min(from.recv_avail().unwrap_or(0), to.send_avail().unwrap_or(0));
+	while n > 0 {
+		let len = cmp::min(n, buf.len());
+		let read = from.recv_slice(&mut buf[..len], executor);
+		let sent = to.send_slice(&buf[..read], executor);
+		debug_assert_eq!((read, sent), (len, len));
+		n -= len;
+	}
+	if let Connection::Killed = from {
+		if let Some(reset) = to.reset(executor) {
+			reset();
+		}
+	} else if !from.connecting() && !from.recvable() {
+		// no longer receiving: its remote has closed, or it's closed
+		if let Some(close) = to.close(executor) {
+			close();
+		}
+	}
+	// nowhere for what `to` receives to go
+	if from.is_terminal() {
+		while to.recv_slice(&mut buf, executor) > 0 {}
+	}
+}
@@ -0,0 +1,100 @@
+use super::*;
+use std::time;
+
+/// Gracefully close a collection of [`Connection`]s, resetting any that haven't reached [`Connection::Closed`] by a deadline.
+///
+/// Like the connections themselves, this should be polled as instructed by the [`Notifier`] until it returns the outcome, or else [`kill`](Shutdown::kill)ed; it panics if dropped before either.
+#[derive(Debug)]
+pub struct Shutdown {
+	connections: Vec<Connection>,
+	deadline: time::Instant,
+	timer: Timer,
+	outcome: ShutdownOutcome,
+}
+/// How many connections were gracefully closed, reset at the deadline, or failed, by a [`Shutdown`].
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct ShutdownOutcome {
+	/// Reached [`Connection::Closed`].
+	pub closed: usize,
+	/// Were reset, with [`Connection::reset`], as they hadn't closed by the deadline or were [`kill`](Shutdown::kill)ed.
+	pub reset: usize,
+	/// Reached [`Connection::Killed`] of their own accord, by a connection error.
+	pub errored: usize,
+}
+impl Shutdown {
+	pub fn new(
+		connections: impl IntoIterator<Item = Connection>, timeout: time::Duration,
+		executor: &impl Notifier,
+	) -> Self {
+		let mut connections: Vec<Connection> = connections.into_iter().collect();
+		for connection in &mut connections {
+			if let Some(close) = connection.close(executor) {
+				close();
+			}
+		}
+		let deadline = time::Instant::now() + timeout;
+		let mut timer = Timer::new();
+		timer.set(deadline, executor);
+		Self {
+			connections,
+			deadline,
+			timer,
+			outcome: ShutdownOutcome::default(),
+		}
+	}
+	/// Returns the outcome once every connection has reached [`Connection::Closed`] or [`Connection::Killed`].
+	pub fn poll(&mut self, executor: &impl Notifier) -> Option<ShutdownOutcome> {
+		let expired = time::Instant::now() >= self.deadline;
+		let mut i = 0;
+		while i < self.connections.len() {
+			let connection = &mut self.connections[i];
+			connection.poll(executor);
+			match connection {
+				Connection::Closed => self.outcome.closed += 1,
+				Connection::Killed => self.outcome.errored += 1,
+				_ if expired => {
+					if let Some(reset) = connection.reset(executor) {
+						reset();
+					}
+					self.outcome.reset += 1;
+				}
+				_ => {
+					i += 1;
+					continue;
+				}
+			}
+			let _ = self.connections.swap_remove(i);
+		}
+		if self.connections.is_empty() {
+			self.timer.cancel(executor);
+			Some(self.outcome)
+		} else {
+			None
+		}
+	}
+	/// Reset the connections yet to close without waiting for the deadline, returning the outcome.
+	pub fn kill(mut self, executor: &impl Notifier) -> ShutdownOutcome {
+		for mut connection in self.connections.drain(..) {
+			match connection {
+				Connection::Closed => self.outcome.closed += 1,
+				Connection::Killed => self.outcome.errored += 1,
+				_ => {
+					if let Some(reset) = connection.reset(executor) {
+						reset();
+					}
+					self.outcome.reset += 1;
+				}
+			}
+		}
+		self.timer.cancel(executor);
+		self.outcome
+	}
+}
+impl Drop for Shutdown {
+	fn drop(&mut self) {
+		assert!(
+			self.connections.is_empty(),
+			"Don't drop Shutdown before it has returned its outcome"
+		);
+	}
+}
@@ -1,44 +1,168 @@
 use super::*;
 #[cfg(unix)]
-use nix::{cmsg_space, sys::socket, sys::uio, unistd};
-use std::os;
+use nix::{cmsg_space, errno, sys::socket, sys::uio, unistd};
 #[cfg(unix)]
 use std::os::unix::io::IntoRawFd;
+use std::{fmt, fs, io, os, path};
+
+/// The maximum length of the payload that can accompany a forwarded fd.
+pub const FORWARD_PAYLOAD_MAX: usize = 1024;
+// The payload a Listener forwards accepted connections with, marking that none of them has been read.
+pub(crate) const FORWARD_UNREAD: &[u8] = b"tcp_typed unread";
 
 #[derive(Clone)]
 pub struct SocketForwarder(Fd);
-pub struct SocketForwardee(pub(crate) Fd);
+pub struct SocketForwardee(pub(crate) Fd, pub(crate) Option<SocketPath>);
 pub fn socket_forwarder() -> (SocketForwarder, SocketForwardee) {
 	let (send, receive) = os::unix::net::UnixDatagram::pair().unwrap();
 	receive.set_nonblocking(true).unwrap();
 	(
 		SocketForwarder(send.into_raw_fd()),
-		SocketForwardee(receive.into_raw_fd()),
+		SocketForwardee(receive.into_raw_fd(), None),
 	)
 }
+/// Like [`socket_forwarder`], but over an `AF_UNIX` socket bound at `path`, which other processes can then [`connect`](SocketForwarder::connect) to.
+///
+/// [`socket_forwarder`]'s anonymous pair can only be shared with related processes, by fork and exec. To forward between unrelated processes, the receiving one would instead [`bind`](SocketForwardee::bind) and the sending ones connect.
+///
+/// # Errors
+///
+/// If the socket can't be bound at `path`, such as if it already exists.
+pub fn socket_forwarder_named(path: &path::Path) -> io::Result<(SocketForwarder, SocketForwardee)> {
+	let receive = SocketForwardee::bind(path)?;
+	let send = SocketForwarder::connect(path)?;
+	Ok((send, receive))
+}
+
+// The file of a bound SocketForwardee, removed once it's done with.
+#[derive(Debug)]
+pub(crate) struct SocketPath(path::PathBuf);
+impl Drop for SocketPath {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.0);
+	}
+}
+
 impl SocketForwarder {
-	pub fn send(&self, fd: Fd, copy: bool) -> Result<(), nix::Error> {
-		let iov = [uio::IoVec::from_slice(&[])];
+	/// Connect to the [`SocketForwardee`] bound at `path`, typically by another process.
+	///
+	/// # Errors
+	///
+	/// If there's nothing bound at `path` to connect to.
+	pub fn connect(path: &path::Path) -> io::Result<Self> {
+		let socket = os::unix::net::UnixDatagram::unbound()?;
+		socket.connect(path)?;
+		Ok(Self(socket.into_raw_fd()))
+	}
+	/// Send `fd` to the [`SocketForwardee`]. Unless `copy`, ownership of `fd` passes to this on success, and it's closed.
+	///
+	/// On failure (for example the receiver has gone, or its buffer is full) ownership of `fd` is handed back in the [`SendError`], and it's for the caller to close or reuse it.
+	pub fn send(&self, fd: Fd, copy: bool) -> Result<(), SendError> {
+		self.send_with(fd, copy, &[])
+	}
+	/// Like [`send`](SocketForwarder::send), with `payload` (of at most [`FORWARD_PAYLOAD_MAX`] bytes) delivered alongside `fd`, as returned by [`SocketForwardee::recv_with`].
+	///
+	/// # Errors
+	///
+	/// As [`send`](SocketForwarder::send).
+	///
+	/// # Panics
+	///
+	/// If `payload` is longer than [`FORWARD_PAYLOAD_MAX`], or closing `fd` once sent fails.
+	pub fn send_with(&self, fd: Fd, copy: bool, payload: &[u8]) -> Result<(), SendError> {
+		assert!(payload.len() <= FORWARD_PAYLOAD_MAX);
+		let iov = [uio::IoVec::from_slice(payload)];
 		let fds = [fd];
 		let cmsg = [socket::ControlMessage::ScmRights(&fds)];
-		socket::sendmsg(self.0, &iov, &cmsg, socket::MsgFlags::empty(), None).map(|x| {
-			assert_eq!(x, 0);
-			if !copy {
-				// This is hilarious. Needed to stop the socket being left in a corrupted state whereby bytes increment tcpi_rxbytes but can't actually be read!
-				#[cfg(any(target_os = "macos", target_os = "ios"))]
-				let _ = std::thread::spawn(move || {
-					std::thread::sleep(std::time::Duration::from_millis(1000));
+		socket::sendmsg(self.0, &iov, &cmsg, socket::MsgFlags::empty(), None)
+			.map(|x| {
+				assert_eq!(x, payload.len());
+				if !copy {
+					// This is hilarious. Needed to stop the socket being left in a corrupted state whereby bytes increment tcpi_rxbytes but can't actually be read!
+					#[cfg(any(target_os = "macos", target_os = "ios"))]
+					close_delayed(fd);
+					#[cfg(not(any(target_os = "macos", target_os = "ios")))]
 					unistd::close(fd).unwrap();
-				});
-				#[cfg(not(any(target_os = "macos", target_os = "ios")))]
-				unistd::close(fd).unwrap();
-			}
-		})
+				}
+			})
+			.map_err(|error| SendError {
+				fd,
+				error: nix_to_io(error),
+			})
+	}
+}
+// Close `fd` a second after it's been forwarded. Rather than a thread per fd, which churns under high forwarding rates, a single thread shared by all forwarders closes them in turn. It's started by the first forward and, as the sender in the static is never dropped, lives for the rest of the process, blocked on the channel while there's nothing to close; any fds still waiting at exit are closed by the exit.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn close_delayed(fd: Fd) {
+	static CLOSER: std::sync::OnceLock<std::sync::mpsc::Sender<(time::Instant, Fd)>> =
+		std::sync::OnceLock::new();
+	let closer = CLOSER.get_or_init(|| {
+		let (sender, receiver) = std::sync::mpsc::channel::<(time::Instant, Fd)>();
+		let _ = std::thread::Builder::new()
+			.name(String::from("tcp_typed-close"))
+			.spawn(move || {
+				// deadlines are queued in order, so any that have passed while sleeping are closed straight away
+				for (deadline, fd) in receiver {
+					std::thread::sleep(deadline.saturating_duration_since(time::Instant::now()));
+					// not unwrapped, as a panic here would end the thread and with it every later close
+					let _ = unistd::close(fd);
+				}
+			})
+			.unwrap();
+		sender
+	});
+	closer
+		.send((time::Instant::now() + time::Duration::from_secs(1), fd))
+		.unwrap();
+}
+
+/// A failed [`SocketForwarder::send`], returning ownership of the fd that wasn't sent.
+pub struct SendError {
+	pub fd: Fd,
+	pub error: io::Error,
+}
+impl SendError {
+	/// Close the fd with zero linger, such that if it's a TCP connection the remote is promptly rejected with RST rather than an orderly FIN. Failing to set the linger, as for fds that aren't sockets, or to close, as when the connection has already been reset, isn't an error: either way the fd is released.
+	pub fn reset(self) {
+		set_reset_on_close(self.fd);
+		let _ = unistd::close(self.fd);
+	}
+}
+impl fmt::Debug for SendError {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("SendError")
+			.field("fd", &self.fd)
+			.field("error", &self.error)
+			.finish()
 	}
 }
 impl SocketForwardee {
-	pub fn recv(&self) -> Result<Fd, nix::Error> {
-		let mut buf = [0; 8];
+	/// Bind an `AF_UNIX` datagram socket at `path` to receive from [`SocketForwarder::connect`]s. The socket file is removed when this, or the [`Listener`] it's given to, is dropped or closed.
+	///
+	/// # Errors
+	///
+	/// If the socket can't be bound at `path`, such as if it already exists.
+	pub fn bind(path: &path::Path) -> io::Result<Self> {
+		let socket = os::unix::net::UnixDatagram::bind(path)?;
+		let path = SocketPath(path.to_owned());
+		socket.set_nonblocking(true)?;
+		Ok(Self(socket.into_raw_fd(), Some(path)))
+	}
+	/// Receive a forwarded fd, discarding any payload.
+	pub fn recv(&self) -> io::Result<Fd> {
+		self.recv_with().map(|(fd, _payload)| fd)
+	}
+	/// Receive a forwarded fd, along with the payload it was sent with by [`SocketForwarder::send_with`].
+	///
+	/// # Errors
+	///
+	/// `EAGAIN` if there's nothing to receive. A message that was truncated, or otherwise isn't a single fd as sent by [`SocketForwarder`], is discarded (closing any fds it carried) and `EBADMSG` returned.
+	pub fn recv_with(&self) -> io::Result<(Fd, Vec<u8>)> {
+		self.recv_forward().map_err(nix_to_io)
+	}
+	// As recv_with, for the Listener to match on the errno.
+	pub(crate) fn recv_forward(&self) -> Result<(Fd, Vec<u8>), nix::Error> {
+		let mut buf = [0; FORWARD_PAYLOAD_MAX];
 		let iovec = [uio::IoVec::from_mut_slice(&mut buf)];
 		let mut space = cmsg_space!([Fd; 2]);
 		socket::recvmsg(
@@ -47,16 +171,29 @@ impl SocketForwardee {
 			Some(&mut space),
 			socket::MsgFlags::MSG_DONTWAIT,
 		)
-		.map(|msg| {
-			let mut iter = msg.cmsgs();
-			match (iter.next(), iter.next()) {
-				(Some(socket::ControlMessageOwned::ScmRights(fds)), None) => {
-					assert_eq!(msg.bytes, 0);
-					assert_eq!(fds.len(), 1);
-					fds[0]
+		.and_then(|msg| {
+			let mut fds = Vec::new();
+			let mut unexpected = false;
+			for cmsg in msg.cmsgs() {
+				match cmsg {
+					socket::ControlMessageOwned::ScmRights(received) => fds.extend(received),
+					_ => unexpected = true,
+				}
+			}
+			// truncated, or not as sent by SocketForwarder; close whatever fds did arrive so they don't leak
+			if unexpected
+				|| fds.len() != 1
+				|| msg
+					.flags
+					.intersects(socket::MsgFlags::MSG_CTRUNC | socket::MsgFlags::MSG_TRUNC)
+			{
+				for fd in fds {
+					let _ = unistd::close(fd);
 				}
-				_ => panic!(),
+				return Err(nix::Error::Sys(errno::Errno::EBADMSG));
 			}
+			Ok((fds[0], msg.bytes))
 		})
+		.map(|(fd, len)| (fd, buf[..len].to_vec()))
 	}
 }
@@ -0,0 +1,39 @@
+//! Raw `getsockopt(2)`/`setsockopt(2)` for options not (yet) wrapped by `nix::sys::socket::sockopt`.
+
+use super::Fd;
+use nix::{errno, libc};
+use std::{convert::TryInto, mem, ptr};
+
+pub(crate) fn set<T: Copy>(
+	fd: Fd, level: libc::c_int, name: libc::c_int, value: T,
+) -> Result<(), nix::Error> {
+	let res = unsafe {
+		libc::setsockopt(
+			fd,
+			level,
+			name,
+			ptr::addr_of!(value).cast(),
+			size_of::<T>().try_into().unwrap(),
+		)
+	};
+	errno::Errno::result(res).map(drop)
+}
+
+// the options read so far are all Linux's, bar TCP_MAXSEG which macOS reports in TCP_CONNECTION_INFO
+#[cfg_attr(any(target_os = "macos", target_os = "ios"), allow(dead_code))]
+pub(crate) fn get<T: Copy>(fd: Fd, level: libc::c_int, name: libc::c_int) -> Result<T, nix::Error> {
+	let mut value = mem::MaybeUninit::<T>::uninit();
+	let mut len: libc::socklen_t = size_of::<T>().try_into().unwrap();
+	let res = unsafe {
+		libc::getsockopt(
+			fd,
+			level,
+			name,
+			value.as_mut_ptr().cast(),
+			ptr::addr_of_mut!(len),
+		)
+	};
+	let _ = errno::Errno::result(res)?;
+	assert_eq!(len, size_of::<T>().try_into().unwrap());
+	Ok(unsafe { value.assume_init() })
+}
@@ -0,0 +1,393 @@
+//! Connecting through a SOCKS5 proxy ([RFC 1928](https://tools.ietf.org/html/rfc1928)).
+
+use super::*;
+use log::trace;
+use std::{collections::VecDeque, convert::TryInto, fmt, mem, net};
+
+/// The destination to ask a SOCKS5 proxy to connect to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Socks5Target {
+	Addr(net::SocketAddr),
+	/// A hostname, resolved by the proxy. At most 255 bytes.
+	Domain(String, u16),
+}
+
+/// The authentication to offer a SOCKS5 proxy.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Socks5Auth {
+	None,
+	/// Username/password authentication ([RFC 1929](https://tools.ietf.org/html/rfc1929)). Each at most 255 bytes. No authentication is also offered.
+	UserPass {
+		username: String,
+		password: String,
+	},
+}
+impl fmt::Debug for Socks5Auth {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::None => fmt.write_str("None"),
+			Self::UserPass { username, .. } => fmt
+				.debug_struct("UserPass")
+				.field("username", username)
+				.finish(),
+		}
+	}
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Phase {
+	Method,
+	Auth,
+	Reply,
+}
+
+#[derive(Debug)]
+enum Inner {
+	Connecter(Connecter),
+	Connected(Connected),
+}
+
+#[derive(Debug)]
+pub enum Socks5Poll {
+	Socks5(Socks5),
+	Connected(Connected),
+	Killed,
+}
+/// Connecting to a SOCKS5 proxy and performing the handshake, after which it becomes a [`Connected`] whose [`remote`](Connected::remote) is the target for [`Socks5Target::Addr`]. For [`Socks5Target::Domain`] the proxy doesn't tell us the address it resolved, so it remains the proxy; the target as requested is [`target`](Socks5::target).
+///
+/// If the proxy refuses the connection, or closes during the handshake, it is killed.
+pub struct Socks5 {
+	// only None transiently, during poll
+	inner: Option<Inner>,
+	proxy: net::SocketAddr,
+	target: Socks5Target,
+	auth: Socks5Auth,
+	phase: Phase,
+	send: VecDeque<u8>,
+	recv: Vec<u8>,
+	since: time::Instant,
+}
+impl Socks5 {
+	/// # Panics
+	///
+	/// If the domain, username or password is longer than 255 bytes.
+	pub fn new(
+		local: net::SocketAddr, proxy: net::SocketAddr, target: Socks5Target, auth: Socks5Auth,
+		config: &ConnectionConfig, executor: &impl Notifier,
+	) -> Socks5Poll {
+		if let Socks5Target::Domain(domain, _) = &target {
+			assert!(domain.len() <= 255, "domain too long");
+		}
+		if let Socks5Auth::UserPass { username, password } = &auth {
+			assert!(username.len() <= 255, "username too long");
+			assert!(password.len() <= 255, "password too long");
+		}
+		trace!(
+			"Socks5 connect {:?} via {}",
+			target,
+			format_remote(proxy, config)
+		);
+		let mut send = VecDeque::new();
+		match auth {
+			Socks5Auth::None => send.extend(&[5, 1, 0]),
+			Socks5Auth::UserPass { .. } => send.extend(&[5, 2, 0, 2]),
+		}
+		let inner = match Connecter::new_with(local, proxy, config, executor) {
+			ConnecterPoll::Connecter(connecter) => Inner::Connecter(connecter),
+			ConnecterPoll::Connected(connected) => Inner::Connected(connected),
+			ConnecterPoll::RemoteClosed(remote_closed) => {
+				remote_closed.kill(executor);
+				return Socks5Poll::Killed;
+			}
+			ConnecterPoll::Killed => return Socks5Poll::Killed,
+		};
+		Self {
+			inner: Some(inner),
+			proxy,
+			target,
+			auth,
+			phase: Phase::Method,
+			send,
+			recv: Vec::new(),
+			since: time::Instant::now(),
+		}
+		.poll(executor)
+	}
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Socks5::kill_in_place).
+	pub fn poll(mut self, executor: &impl Notifier) -> Socks5Poll {
+		let poll = match self.inner.take().unwrap() {
+			Inner::Connecter(connecter) => connecter.poll(executor),
+			Inner::Connected(connected) => match connected.poll(executor) {
+				ConnectedPoll::Connected(connected) => ConnecterPoll::Connected(connected),
+				ConnectedPoll::RemoteClosed(remote_closed) => {
+					ConnecterPoll::RemoteClosed(remote_closed)
+				}
+				ConnectedPoll::Reset(reset) => {
+					reset.kill(executor);
+					ConnecterPoll::Killed
+				}
+				ConnectedPoll::Killed => ConnecterPoll::Killed,
+			},
+		};
+		let mut connected = match poll {
+			ConnecterPoll::Connecter(connecter) => {
+				self.inner = Some(Inner::Connecter(connecter));
+				return Socks5Poll::Socks5(self);
+			}
+			ConnecterPoll::Connected(connected) => connected,
+			ConnecterPoll::RemoteClosed(remote_closed) => {
+				trace!("Socks5 proxy closed {:?}", self.target);
+				remote_closed.kill(executor);
+				return Socks5Poll::Killed;
+			}
+			ConnecterPoll::Killed => return Socks5Poll::Killed,
+		};
+		loop {
+			while !self.send.is_empty() {
+				if let Some(send) = connected.send(executor) {
+					send(self.send.pop_front().unwrap());
+				} else {
+					break;
+				}
+			}
+			let mut len = needed(self.phase, &self.recv);
+			while self.recv.len() < len {
+				if let Some(recv) = connected.recv(executor) {
+					self.recv.push(recv());
+					len = needed(self.phase, &self.recv);
+				} else {
+					break;
+				}
+			}
+			if self.recv.len() < len {
+				self.inner = Some(Inner::Connected(connected));
+				return Socks5Poll::Socks5(self);
+			}
+			let reply = mem::take(&mut self.recv);
+			match (self.phase, &*reply) {
+				(Phase::Method, [5, 0]) | (Phase::Auth, [1, 0]) => {
+					self.request();
+					self.phase = Phase::Reply;
+				}
+				(Phase::Method, [5, 2]) => {
+					if let Socks5Auth::UserPass { username, password } = &self.auth {
+						self.send.push_back(1);
+						self.send.push_back(username.len().try_into().unwrap());
+						self.send.extend(username.as_bytes());
+						self.send.push_back(password.len().try_into().unwrap());
+						self.send.extend(password.as_bytes());
+						self.phase = Phase::Auth;
+					} else {
+						return self.fail(connected, &reply, executor);
+					}
+				}
+				(Phase::Reply, reply) if succeeded(reply) => {
+					trace!(
+						"Socks5 connected {:?} via {}",
+						self.target,
+						connected.remote()
+					);
+					if let Socks5Target::Addr(addr) = self.target {
+						connected.set_remote(addr);
+					}
+					connected.entered_state();
+					return Socks5Poll::Connected(connected);
+				}
+				_ => return self.fail(connected, &reply, executor),
+			}
+		}
+	}
+	/// # Panics
+	///
+	/// If it's been poisoned by [`kill_in_place`](Socks5::kill_in_place).
+	pub fn kill(self, executor: &impl Notifier) {
+		match self.inner.unwrap() {
+			Inner::Connecter(connecter) => connecter.kill(executor),
+			Inner::Connected(connected) => connected.kill(executor),
+		}
+	}
+	/// Like [`kill`](Socks5::kill), but by reference, for when this is held behind `&mut`. This is then poisoned: it mustn't be used again, but can be dropped without panicking.
+	///
+	/// # Panics
+	///
+	/// If it's already been killed.
+	pub fn kill_in_place(&mut self, executor: &impl Notifier) {
+		match self.inner.take().expect("Socks5 killed twice") {
+			Inner::Connecter(connecter) => connecter.kill(executor),
+			Inner::Connected(connected) => connected.kill(executor),
+		}
+	}
+	/// The readiness this is waiting on, as of the last poll. Nothing once killed in place.
+	#[must_use]
+	pub fn interest(&self) -> Interest {
+		match &self.inner {
+			Some(Inner::Connecter(connecter)) => connecter.interest(),
+			Some(Inner::Connected(connected)) => Interest {
+				read: true,
+				write: !self.send.is_empty() || connected.interest().write,
+			},
+			None => Interest::default(),
+		}
+	}
+	/// The remote address, which until connected through it is the [`proxy`](Socks5::proxy).
+	#[must_use]
+	#[inline(always)]
+	pub fn remote(&self) -> net::SocketAddr {
+		self.proxy
+	}
+	/// The address of the proxy.
+	#[must_use]
+	#[inline(always)]
+	pub fn proxy(&self) -> net::SocketAddr {
+		self.proxy
+	}
+	// The socket, if there is one.
+	#[inline(always)]
+	pub(crate) fn fd(&self) -> Option<Fd> {
+		match &self.inner {
+			Some(Inner::Connecter(connecter)) => connecter.fd(),
+			Some(Inner::Connected(connected)) => Some(connected.fd()),
+			None => None,
+		}
+	}
+	/// The destination requested of the proxy.
+	#[must_use]
+	#[inline(always)]
+	pub fn target(&self) -> &Socks5Target {
+		&self.target
+	}
+	fn request(&mut self) {
+		self.send.extend(&[5, 1, 0]);
+		let port = match &self.target {
+			Socks5Target::Addr(net::SocketAddr::V4(addr)) => {
+				self.send.push_back(1);
+				self.send.extend(&addr.ip().octets());
+				addr.port()
+			}
+			Socks5Target::Addr(net::SocketAddr::V6(addr)) => {
+				self.send.push_back(4);
+				self.send.extend(&addr.ip().octets());
+				addr.port()
+			}
+			Socks5Target::Domain(domain, port) => {
+				self.send.push_back(3);
+				self.send.push_back(domain.len().try_into().unwrap());
+				self.send.extend(domain.as_bytes());
+				*port
+			}
+		};
+		self.send.extend(&port.to_be_bytes());
+	}
+	fn fail(self, connected: Connected, reply: &[u8], executor: &impl Notifier) -> Socks5Poll {
+		trace!(
+			"Socks5 refused {:?} via {}: {:?} {:?}",
+			self.target,
+			connected.remote(),
+			self.phase,
+			reply
+		);
+		connected.kill(executor);
+		Socks5Poll::Killed
+	}
+}
+
+// The length of the reply being received, as far as can be known from what's been received of it so far.
+fn needed(phase: Phase, recv: &[u8]) -> usize {
+	match (phase, recv) {
+		(Phase::Method | Phase::Auth, _) => 2,
+		// fail early on error, as some proxies send a truncated reply
+		(Phase::Reply, [_, rep, ..]) if *rep != 0 => recv.len(),
+		(Phase::Reply, [_, _, _, 1, ..]) => 4 + 4 + 2,
+		(Phase::Reply, [_, _, _, 4, ..]) => 4 + 16 + 2,
+		(Phase::Reply, [_, _, _, 3, len, ..]) => 5 + usize::from(*len) + 2,
+		// the domain's length is yet to arrive
+		(Phase::Reply, [_, _, _, 3]) => 5,
+		(Phase::Reply, [_, _, _, _, ..]) => recv.len(),
+		(Phase::Reply, _) => 5,
+	}
+}
+
+// Whether a complete reply to the CONNECT request reports success.
+fn succeeded(reply: &[u8]) -> bool {
+	matches!(reply, [5, 0, _, 1 | 3 | 4, ..])
+}
+
+impl StateInfo for Socks5 {
+	#[inline(always)]
+	fn time_in_state(&self) -> time::Duration {
+		self.since.elapsed()
+	}
+	#[inline(always)]
+	fn origin(&self) -> Origin {
+		Origin::Dialer
+	}
+	#[inline(always)]
+	fn label(&self) -> Option<&str> {
+		match &self.inner {
+			Some(Inner::Connecter(connecter)) => connecter.label(),
+			Some(Inner::Connected(connected)) => connected.label(),
+			None => None,
+		}
+	}
+}
+impl fmt::Debug for Socks5 {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("Socks5")
+			.field("inner", &self.inner)
+			.field("proxy", &self.proxy)
+			.field("target", &self.target)
+			.field("auth", &self.auth)
+			.field("phase", &self.phase)
+			.field("send", &self.send)
+			.field("recv", &self.recv)
+			.field("since", &self.since)
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{needed, succeeded, Phase};
+
+	#[test]
+	fn reply_success() {
+		let ipv4 = [5, 0, 0, 1, 127, 0, 0, 1, 0x1f, 0x90];
+		assert_eq!(needed(Phase::Reply, &ipv4), ipv4.len());
+		assert!(succeeded(&ipv4));
+		let ipv6 = [&[5, 0, 0, 4][..], &[0; 16], &[0x1f, 0x90]].concat();
+		assert_eq!(needed(Phase::Reply, &ipv6), ipv6.len());
+		assert!(succeeded(&ipv6));
+		let domain = [&[5, 0, 0, 3, 9][..], b"localhost", &[0x1f, 0x90]].concat();
+		assert_eq!(needed(Phase::Reply, &domain), domain.len());
+		assert!(succeeded(&domain));
+	}
+
+	#[test]
+	fn reply_error() {
+		// connection refused, failing as soon as the code arrives
+		assert_eq!(needed(Phase::Reply, &[5, 5]), 2);
+		assert!(!succeeded(&[5, 5]));
+		assert!(!succeeded(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]));
+		// unknown address type
+		assert_eq!(needed(Phase::Reply, &[5, 0, 0, 2]), 4);
+		assert!(!succeeded(&[5, 0, 0, 2]));
+		// wrong version
+		assert!(!succeeded(&[4, 0, 0, 1, 0, 0, 0, 0, 0, 0]));
+	}
+
+	#[test]
+	fn reply_truncated() {
+		let ipv4 = [5, 0, 0, 1, 127, 0, 0, 1, 0x1f, 0x90];
+		for len in 0..ipv4.len() {
+			assert!(needed(Phase::Reply, &ipv4[..len]) > len, "{}", len);
+		}
+		let domain = [&[5, 0, 0, 3, 9][..], b"localhost", &[0x1f, 0x90]].concat();
+		for len in 0..domain.len() {
+			assert!(needed(Phase::Reply, &domain[..len]) > len, "{}", len);
+		}
+		assert_eq!(needed(Phase::Method, &[5]), 2);
+		assert_eq!(needed(Phase::Auth, &[]), 2);
+	}
+}
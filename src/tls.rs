@@ -0,0 +1,200 @@
+//! TLS over a [`Connection`], using [`rustls`].
+
+use super::*;
+use log::trace;
+use std::{fmt, io, io::Read, io::Write};
+
+/// A [`Connection`] carrying a TLS session, with the handshake, encryption and decryption driven by [`poll`](TlsConnection::poll).
+///
+/// Like a [`Connection`], this must be closed and polled until [`closed`](TlsConnection::closed), or killed, before being dropped.
+pub struct TlsConnection {
+	connection: Connection,
+	tls: rustls::Connection,
+	closing: bool,
+	remote_closed: bool,
+	error: Option<rustls::Error>,
+}
+impl TlsConnection {
+	/// Wrap `connection`, which would typically be newly connecting, in the TLS session `tls`.
+	pub fn new(connection: Connection, tls: impl Into<rustls::Connection>) -> Self {
+		Self {
+			connection,
+			tls: tls.into(),
+			closing: false,
+			remote_closed: false,
+			error: None,
+		}
+	}
+	/// Drive the underlying connection and the TLS session. Does nothing once the connection is [`is_terminal`](Connection::is_terminal).
+	pub fn poll(&mut self, executor: &impl Notifier) {
+		if self.connection.is_terminal() {
+			return;
+		}
+		self.connection.poll(executor);
+		while self.error.is_none() && self.tls.wants_read() {
+			match self
+				.tls
+				.read_tls(&mut RecvCiphertext(&mut self.connection, executor))
+			{
+				Err(_) => break,
+				Ok(read) => {
+					// also on EOF, so rustls knows the session has ended, and whether it was truncated
+					if let Err(err) = self.tls.process_new_packets() {
+						// the alert rustls queued is flushed below before closing
+						trace!("TlsConnection err {:?} {:?}", self.connection.label(), err);
+						self.error = Some(err);
+						self.closing = true;
+					}
+					if read == 0 {
+						break;
+					}
+				}
+			}
+		}
+		// also flushes anything the session wants to write in response to what was read, such as handshake messages and key updates
+		while self.tls.wants_write() {
+			match self
+				.tls
+				.write_tls(&mut SendCiphertext(&mut self.connection, executor))
+			{
+				Ok(0) | Err(_) => break,
+				Ok(_) => (),
+			}
+		}
+		if self.closing && !self.tls.wants_write() {
+			if let Some(close) = self.connection.close(executor) {
+				close();
+			}
+		}
+	}
+	/// Read decrypted data into `buf`, returning the number of bytes read.
+	///
+	/// # Errors
+	///
+	/// If the TLS stream is invalid, such as [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) if the connection closed without `close_notify`, when the data received so far may have been truncated. The remote is considered closed thereafter.
+	pub fn recv_slice(&mut self, buf: &mut [u8], executor: &impl Notifier) -> io::Result<usize> {
+		match self.tls.reader().read(buf) {
+			Ok(0) if !buf.is_empty() => {
+				self.remote_closed = true;
+				Ok(0)
+			}
+			Ok(read) => {
+				if read > 0 {
+					executor.queue();
+				}
+				Ok(read)
+			}
+			Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+			Err(err) => {
+				trace!(
+					"TlsConnection recv err {:?} {:?}",
+					self.connection.label(),
+					err
+				);
+				self.remote_closed = true;
+				Err(err)
+			}
+		}
+	}
+	/// Queue `buf` to be encrypted and sent, returning the number of bytes accepted.
+	///
+	/// # Errors
+	///
+	/// If rustls refuses the plaintext.
+	pub fn send_slice(&mut self, buf: &[u8], executor: &impl Notifier) -> io::Result<usize> {
+		if self.closing {
+			return Ok(0);
+		}
+		let written = self.tls.writer().write(buf)?;
+		if written > 0 {
+			executor.queue();
+		}
+		Ok(written)
+	}
+	/// Send `close_notify`, then close the underlying connection once everything has been flushed to it.
+	pub fn close(&mut self, executor: &impl Notifier) {
+		if !self.closing {
+			self.tls.send_close_notify();
+			self.closing = true;
+			executor.queue();
+		}
+	}
+	/// Kill the underlying connection, if it's yet to be closed or killed.
+	pub fn kill(&mut self, executor: &impl Notifier) {
+		if let Some(kill) = self.connection.kill(executor) {
+			kill();
+		}
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn is_handshaking(&self) -> bool {
+		self.tls.is_handshaking()
+	}
+	/// Whether the remote has sent `close_notify` and all decrypted data has been read, or [`recv_slice`](TlsConnection::recv_slice) has returned an error.
+	#[must_use]
+	#[inline(always)]
+	pub fn remote_closed(&self) -> bool {
+		self.remote_closed
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn closed(&self) -> bool {
+		self.connection.closed()
+	}
+	/// The TLS error that ended the session, if any.
+	#[must_use]
+	#[inline(always)]
+	pub fn error(&self) -> Option<&rustls::Error> {
+		self.error.as_ref()
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn connection(&self) -> &Connection {
+		&self.connection
+	}
+	#[must_use]
+	#[inline(always)]
+	pub fn tls(&self) -> &rustls::Connection {
+		&self.tls
+	}
+}
+impl fmt::Debug for TlsConnection {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt.debug_struct("TlsConnection")
+			.field("connection", &self.connection)
+			.field("tls", &self.tls)
+			.field("closing", &self.closing)
+			.field("remote_closed", &self.remote_closed)
+			.field("error", &self.error)
+			.finish()
+	}
+}
+
+// Ciphertext from the connection's receive buffer. EOF only once the remote has closed, as to rustls that's the end of the session.
+struct RecvCiphertext<'a, N: Notifier>(&'a mut Connection, &'a N);
+impl<N: Notifier> Read for RecvCiphertext<'_, N> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self.0.recv_avail() {
+			Some(0) => Err(io::ErrorKind::WouldBlock.into()),
+			Some(_) => Ok(self.0.recv_slice(buf, self.1)),
+			None if self.0.connecting() => Err(io::ErrorKind::WouldBlock.into()),
+			None => Ok(0),
+		}
+	}
+}
+
+// Ciphertext into the connection's send buffer.
+struct SendCiphertext<'a, N: Notifier>(&'a mut Connection, &'a N);
+impl<N: Notifier> Write for SendCiphertext<'_, N> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self.0.send_avail() {
+			Some(0) => Err(io::ErrorKind::WouldBlock.into()),
+			Some(_) => Ok(self.0.send_slice(buf, self.1)),
+			None if self.0.connecting() => Err(io::ErrorKind::WouldBlock.into()),
+			None => Err(io::ErrorKind::BrokenPipe.into()),
+		}
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
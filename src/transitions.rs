@@ -0,0 +1,306 @@
+//! The legal transitions between [`Connection`] states, as data, for model-based testing and drawing the state machine. Only with the `testing` feature.
+
+use super::*;
+
+/// Which variant a [`Connection`] is, without its contents.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum StateKind {
+	Connecter,
+	Connectee,
+	ConnecterLocalClosed,
+	ConnecteeLocalClosed,
+	Connected,
+	RemoteClosed,
+	LocalClosed,
+	Closing,
+	Reset,
+	Socks5,
+	Handshaking,
+	Closed,
+	Killed,
+}
+
+/// What a [`Connection`] can transition on.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Event {
+	/// [`Connection::poll`].
+	Poll,
+	/// [`Connection::close`] or [`Connection::close_with_timeout`].
+	Close,
+	/// [`Connection::kill`].
+	Kill,
+	/// [`Connection::handshake`].
+	Handshake,
+}
+
+impl Connection {
+	/// Which state this is in.
+	#[must_use]
+	pub fn kind(&self) -> StateKind {
+		match self {
+			Self::Connecter(_) => StateKind::Connecter,
+			Self::Connectee(_) => StateKind::Connectee,
+			Self::ConnecterLocalClosed(_) => StateKind::ConnecterLocalClosed,
+			Self::ConnecteeLocalClosed(_) => StateKind::ConnecteeLocalClosed,
+			Self::Connected(_) => StateKind::Connected,
+			Self::RemoteClosed(_) => StateKind::RemoteClosed,
+			Self::LocalClosed(_) => StateKind::LocalClosed,
+			Self::Closing(_) => StateKind::Closing,
+			Self::Reset(_) => StateKind::Reset,
+			Self::Socks5(_) => StateKind::Socks5,
+			Self::Handshaking(_) => StateKind::Handshaking,
+			Self::Closed => StateKind::Closed,
+			Self::Killed => StateKind::Killed,
+		}
+	}
+}
+
+/// Every transition a [`Connection`] can take, as `(from, event, to)`. Polls that leave it in the same state are included; the terminal states [`Closed`](StateKind::Closed) and [`Killed`](StateKind::Killed) have none.
+///
+/// This mirrors the `*Poll` enums returned by each state's `poll`, `close` and `handshake`.
+#[must_use]
+#[allow(clippy::similar_names, clippy::too_many_lines)]
+pub fn transitions() -> Vec<(StateKind, Event, StateKind)> {
+	// the states each *Poll enum can yield
+	let connecter_poll = [
+		StateKind::Connecter,
+		StateKind::Connected,
+		StateKind::RemoteClosed,
+		StateKind::Killed,
+	];
+	let connectee_poll = [
+		StateKind::Connectee,
+		StateKind::Connected,
+		StateKind::RemoteClosed,
+		StateKind::Killed,
+	];
+	let connecter_local_closed_poll = [
+		StateKind::ConnecterLocalClosed,
+		StateKind::LocalClosed,
+		StateKind::Closing,
+		StateKind::Closed,
+		StateKind::Killed,
+	];
+	let connectee_local_closed_poll = [
+		StateKind::ConnecteeLocalClosed,
+		StateKind::LocalClosed,
+		StateKind::Closing,
+		StateKind::Closed,
+		StateKind::Killed,
+	];
+	let connected_poll = [
+		StateKind::Connected,
+		StateKind::RemoteClosed,
+		StateKind::Reset,
+		StateKind::Killed,
+	];
+	let remote_closed_poll = [StateKind::RemoteClosed, StateKind::Killed];
+	let local_closed_poll = [
+		StateKind::LocalClosed,
+		StateKind::Closing,
+		StateKind::Reset,
+		StateKind::Closed,
+		StateKind::Killed,
+	];
+	let closing_poll = [StateKind::Closing, StateKind::Closed, StateKind::Killed];
+	let reset_poll = [StateKind::Reset, StateKind::Killed];
+	let socks5_poll = [StateKind::Socks5, StateKind::Connected, StateKind::Killed];
+	let handshaking_poll = [
+		StateKind::Handshaking,
+		StateKind::Connected,
+		StateKind::RemoteClosed,
+		StateKind::Reset,
+		StateKind::Killed,
+	];
+
+	let edges: [(StateKind, Event, &[StateKind]); 17] = [
+		(StateKind::Connecter, Event::Poll, &connecter_poll),
+		(StateKind::Connectee, Event::Poll, &connectee_poll),
+		(
+			StateKind::ConnecterLocalClosed,
+			Event::Poll,
+			&connecter_local_closed_poll,
+		),
+		(
+			StateKind::ConnecteeLocalClosed,
+			Event::Poll,
+			&connectee_local_closed_poll,
+		),
+		(StateKind::Connected, Event::Poll, &connected_poll),
+		(StateKind::RemoteClosed, Event::Poll, &remote_closed_poll),
+		(StateKind::LocalClosed, Event::Poll, &local_closed_poll),
+		(StateKind::Closing, Event::Poll, &closing_poll),
+		(StateKind::Reset, Event::Poll, &reset_poll),
+		(StateKind::Socks5, Event::Poll, &socks5_poll),
+		(StateKind::Handshaking, Event::Poll, &handshaking_poll),
+		(
+			StateKind::Connecter,
+			Event::Close,
+			&connecter_local_closed_poll,
+		),
+		(
+			StateKind::Connectee,
+			Event::Close,
+			&connectee_local_closed_poll,
+		),
+		(StateKind::Connected, Event::Close, &local_closed_poll),
+		(StateKind::RemoteClosed, Event::Close, &closing_poll),
+		(StateKind::Connectee, Event::Handshake, &handshaking_poll),
+		(StateKind::Connected, Event::Handshake, &handshaking_poll),
+	];
+	// everything but the terminal states can be killed
+	let killable = [
+		StateKind::Connecter,
+		StateKind::Connectee,
+		StateKind::ConnecterLocalClosed,
+		StateKind::ConnecteeLocalClosed,
+		StateKind::Connected,
+		StateKind::RemoteClosed,
+		StateKind::LocalClosed,
+		StateKind::Closing,
+		StateKind::Reset,
+		StateKind::Socks5,
+		StateKind::Handshaking,
+	];
+	edges
+		.iter()
+		.flat_map(|&(from, event, to)| to.iter().map(move |&to| (from, event, to)))
+		.chain(
+			killable
+				.iter()
+				.map(|&from| (from, Event::Kill, StateKind::Killed)),
+		)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashSet;
+
+	// The StateKind of each variant of a *Poll enum. The match is exhaustive, so adding a variant fails to compile until it's listed here, and so checked against the graph below.
+	macro_rules! poll_kinds {
+		($poll:ident { $($variant:ident $(($x:tt))? => $kind:ident,)* }) => {{
+			let _exhaustive = |poll: $poll| match poll {
+				$($poll::$variant $(($x))? => StateKind::$kind,)*
+			};
+			[$(StateKind::$kind,)*].iter().copied().collect::<HashSet<_>>()
+		}};
+	}
+
+	#[test]
+	fn poll_variants() {
+		let connecter = poll_kinds!(ConnecterPoll {
+			Connecter(_) => Connecter,
+			Connected(_) => Connected,
+			RemoteClosed(_) => RemoteClosed,
+			Killed => Killed,
+		});
+		let connectee = poll_kinds!(ConnecteePoll {
+			Connectee(_) => Connectee,
+			Connected(_) => Connected,
+			RemoteClosed(_) => RemoteClosed,
+			Killed => Killed,
+		});
+		let connecter_local_closed = poll_kinds!(ConnecterLocalClosedPoll {
+			ConnecterLocalClosed(_) => ConnecterLocalClosed,
+			LocalClosed(_) => LocalClosed,
+			Closing(_) => Closing,
+			Closed => Closed,
+			Killed => Killed,
+		});
+		let connectee_local_closed = poll_kinds!(ConnecteeLocalClosedPoll {
+			ConnecteeLocalClosed(_) => ConnecteeLocalClosed,
+			LocalClosed(_) => LocalClosed,
+			Closing(_) => Closing,
+			Closed => Closed,
+			Killed => Killed,
+		});
+		let connected = poll_kinds!(ConnectedPoll {
+			Connected(_) => Connected,
+			RemoteClosed(_) => RemoteClosed,
+			Reset(_) => Reset,
+			Killed => Killed,
+		});
+		let remote_closed = poll_kinds!(RemoteClosedPoll {
+			RemoteClosed(_) => RemoteClosed,
+			Killed => Killed,
+		});
+		let local_closed = poll_kinds!(LocalClosedPoll {
+			LocalClosed(_) => LocalClosed,
+			Closing(_) => Closing,
+			Reset(_) => Reset,
+			Closed => Closed,
+			Killed => Killed,
+		});
+		let closing = poll_kinds!(ClosingPoll {
+			Closing(_) => Closing,
+			Closed => Closed,
+			Killed => Killed,
+		});
+		let reset = poll_kinds!(ResetPoll {
+			Reset(_) => Reset,
+			Killed => Killed,
+		});
+		let socks5 = poll_kinds!(Socks5Poll {
+			Socks5(_) => Socks5,
+			Connected(_) => Connected,
+			Killed => Killed,
+		});
+		let handshaking = poll_kinds!(HandshakingPoll {
+			Handshaking(_) => Handshaking,
+			Connected(_) => Connected,
+			RemoteClosed(_) => RemoteClosed,
+			Reset(_) => Reset,
+			Killed => Killed,
+		});
+		// what each state's poll, close and handshake return
+		let expected = [
+			(StateKind::Connecter, Event::Poll, &connecter),
+			(StateKind::Connectee, Event::Poll, &connectee),
+			(
+				StateKind::ConnecterLocalClosed,
+				Event::Poll,
+				&connecter_local_closed,
+			),
+			(
+				StateKind::ConnecteeLocalClosed,
+				Event::Poll,
+				&connectee_local_closed,
+			),
+			(StateKind::Connected, Event::Poll, &connected),
+			(StateKind::RemoteClosed, Event::Poll, &remote_closed),
+			(StateKind::LocalClosed, Event::Poll, &local_closed),
+			(StateKind::Closing, Event::Poll, &closing),
+			(StateKind::Reset, Event::Poll, &reset),
+			(StateKind::Socks5, Event::Poll, &socks5),
+			(StateKind::Handshaking, Event::Poll, &handshaking),
+			(StateKind::Connecter, Event::Close, &connecter_local_closed),
+			(StateKind::Connectee, Event::Close, &connectee_local_closed),
+			(StateKind::Connected, Event::Close, &local_closed),
+			(StateKind::RemoteClosed, Event::Close, &closing),
+			(StateKind::Connectee, Event::Handshake, &handshaking),
+			(StateKind::Connected, Event::Handshake, &handshaking),
+		];
+		let transitions = transitions();
+		for (from, event, kinds) in &expected {
+			let graph = transitions
+				.iter()
+				.filter(|&&(from_, event_, _)| (from_, event_) == (*from, *event))
+				.map(|&(_, _, to)| to)
+				.collect::<HashSet<_>>();
+			assert_eq!(&graph, *kinds, "{:?} {:?}", from, event);
+		}
+		// and nothing else but kills
+		let covered = transitions
+			.iter()
+			.filter(|&&(from, event, _)| {
+				event == Event::Kill
+					|| expected
+						.iter()
+						.any(|&(from_, event_, _)| (from_, event_) == (from, event))
+			})
+			.count();
+		assert_eq!(covered, transitions.len());
+	}
+}
@@ -0,0 +1,950 @@
+//! Connections over loopback, driven by a notifier that checks fds are registered and deregistered in step.
+
+use nix::sys::socket;
+use std::{
+	cell::RefCell,
+	collections::HashSet,
+	io::{self, Read, Write},
+	net,
+	os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+	thread, time,
+};
+use tcp_typed::{
+	socket_forwarder, AcceptDecision, Connection, ConnectionConfig, ConnectionWith,
+	HandshakeProgress, HandshakeStream, Listener, ListenerConfig, ProbeResult, Pump, RecvFull,
+	Shutdown, ShutdownOutcome,
+};
+
+#[derive(Default)]
+struct Notifier {
+	fds: RefCell<HashSet<RawFd>>,
+}
+impl tcp_typed::Notifier for Notifier {
+	type InstantSlot = ();
+	fn queue(&self) {}
+	fn add_fd(&self, fd: RawFd) {
+		assert!(self.fds.borrow_mut().insert(fd), "fd {} added twice", fd);
+	}
+	fn remove_fd(&self, fd: RawFd) {
+		assert!(
+			self.fds.borrow_mut().remove(&fd),
+			"fd {} removed but never added",
+			fd
+		);
+	}
+	fn add_instant(&self, _instant: time::Instant) -> Self::InstantSlot {}
+	fn remove_instant(&self, _slot: Self::InstantSlot) {}
+}
+
+const LOCALHOST: net::IpAddr = net::IpAddr::V4(net::Ipv4Addr::LOCALHOST);
+
+// Poll until `done`, panicking if it takes over 10 seconds.
+fn drive(connection: &mut Connection, notifier: &Notifier, done: impl Fn(&Connection) -> bool) {
+	let start = time::Instant::now();
+	while !done(connection) {
+		assert!(
+			start.elapsed() < time::Duration::from_secs(10),
+			"stuck in {:?}",
+			connection
+		);
+		thread::sleep(time::Duration::from_millis(1));
+		connection.poll(notifier);
+	}
+}
+
+// A port on which nothing is listening, so connecting to it is refused. It stays bound until the returned socket is dropped, so that nothing else, such as a connecting socket's ephemeral port, takes it meanwhile.
+fn refused_addr() -> (OwnedFd, net::SocketAddr) {
+	let fd = socket::socket(
+		socket::AddressFamily::Inet,
+		socket::SockType::Stream,
+		socket::SockFlag::empty(),
+		None,
+	)
+	.unwrap();
+	let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+	let addr = socket::InetAddr::from_std(&(LOCALHOST, 0).into());
+	socket::bind(fd.as_raw_fd(), &socket::SockAddr::new_inet(addr)).unwrap();
+	match socket::getsockname(fd.as_raw_fd()).unwrap() {
+		socket::SockAddr::Inet(addr) => (fd, addr.to_std()),
+		addr => panic!("{:?}", addr),
+	}
+}
+
+// Close `stream` with a RST rather than a FIN.
+fn reset(stream: net::TcpStream) {
+	let linger = nix::libc::linger {
+		l_onoff: 1,
+		l_linger: 0,
+	};
+	nix::sys::socket::setsockopt(
+		stream.as_raw_fd(),
+		nix::sys::socket::sockopt::Linger,
+		&linger,
+	)
+	.unwrap();
+}
+
+fn accept(listener: &mut Listener, notifier: &Notifier) -> Connection {
+	let start = time::Instant::now();
+	loop {
+		let mut connections = listener.accept_all(notifier, &mut |_, _| AcceptDecision::Accept);
+		if let Some(connection) = connections.pop() {
+			assert!(connections.is_empty());
+			return connection;
+		}
+		assert!(
+			start.elapsed() < time::Duration::from_secs(10),
+			"nothing to accept"
+		);
+		thread::sleep(time::Duration::from_millis(1));
+	}
+}
+
+// Send every byte value from `from` to `to`.
+fn exchange(from: &mut Connection, to: &mut Connection, notifier: &Notifier) {
+	for byte in 0..=255 {
+		from.send(notifier).unwrap()(byte);
+	}
+	from.poll(notifier);
+	for byte in 0..=255 {
+		drive(to, notifier, |connection| {
+			connection.recv_avail() != Some(0)
+		});
+		assert_eq!(to.recv(notifier).unwrap()(), byte);
+	}
+}
+
+#[test]
+fn dialer_close_before_established() {
+	let notifier = Notifier::default();
+	let (_refused, remote) = refused_addr();
+	let mut connection = Connection::connect((LOCALHOST, 0).into(), remote, &notifier);
+	assert!(connection.connecting(), "{:?}", connection);
+	connection.close(&notifier).unwrap()();
+	drive(&mut connection, &notifier, Connection::is_terminal);
+	assert!(matches!(connection, Connection::Closed), "{:?}", connection);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn dialer_kill_before_established() {
+	let notifier = Notifier::default();
+	let (_refused, remote) = refused_addr();
+	let mut connection = Connection::connect((LOCALHOST, 0).into(), remote, &notifier);
+	assert!(connection.connecting(), "{:?}", connection);
+	connection.kill(&notifier).unwrap()();
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn listener_reset_before_established() {
+	let notifier = Notifier::default();
+	let (mut listener, port) = Listener::new_ephemeral(&LOCALHOST, &notifier).unwrap();
+	let stream = net::TcpStream::connect((LOCALHOST, port)).unwrap();
+	let start = time::Instant::now();
+	let mut accept_hook = |_: &RawFd, _| AcceptDecision::Accept;
+	let connectee = loop {
+		if let Some((_remote, connectee)) = listener.poll(&notifier, &mut accept_hook).next() {
+			break connectee;
+		}
+		assert!(
+			start.elapsed() < time::Duration::from_secs(10),
+			"nothing to accept"
+		);
+		thread::sleep(time::Duration::from_millis(1));
+	};
+	// reset between the accept and the connectee's first poll, so that it errors immediately
+	reset(stream);
+	thread::sleep(time::Duration::from_millis(50));
+	let connection: Connection = connectee(&notifier).into();
+	assert!(matches!(connection, Connection::Killed), "{:?}", connection);
+	assert_eq!(notifier.fds.borrow().len(), 1);
+	listener.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn listener_close_before_established() {
+	let notifier = Notifier::default();
+	let config = ListenerConfig {
+		// such that the connectee waits for the PROXY header, which never comes
+		proxy_protocol: true,
+		..ListenerConfig::default()
+	};
+	let (mut listener, port) =
+		Listener::new_ephemeral_with(&LOCALHOST, &config, &notifier).unwrap();
+	let stream = net::TcpStream::connect((LOCALHOST, port)).unwrap();
+	let mut connection = accept(&mut listener, &notifier);
+	assert!(
+		matches!(connection, Connection::Connectee(_)),
+		"{:?}",
+		connection
+	);
+	connection.close(&notifier).unwrap()();
+	reset(stream);
+	drive(&mut connection, &notifier, Connection::is_terminal);
+	assert_eq!(notifier.fds.borrow().len(), 1);
+	listener.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn recv_full_grow() {
+	let notifier = Notifier::default();
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let config = ConnectionConfig {
+		recv_full: RecvFull::Grow { max: 256 * 1024 },
+		..ConnectionConfig::default()
+	};
+	let mut connection = Connection::connect_with(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&config,
+		&notifier,
+	);
+	let (mut stream, _) = listener.accept().unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	let data: Vec<u8> = (0..160_000_u32).map(|i| (i % 251) as u8).collect();
+	stream.write_all(&data[..60_000]).unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		connection.recv_avail() == Some(60_000)
+	});
+	let mut received = Vec::new();
+	for _ in 0..50_000 {
+		received.push(connection.recv(&notifier).unwrap()());
+	}
+	// wraps around the ring, which is then grown, moving the wrapped contents
+	let writer = {
+		let data = data.clone();
+		thread::spawn(move || stream.write_all(&data[60_000..]).unwrap())
+	};
+	drive(&mut connection, &notifier, |connection| {
+		connection.recv_avail() == Some(110_000)
+	});
+	writer.join().unwrap();
+	while let Some(recv) = connection.recv(&notifier) {
+		received.push(recv());
+	}
+	assert!(received == data);
+	connection.kill(&notifier).unwrap()();
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+#[should_panic(expected = "send_buf must be non-zero")]
+fn zero_send_buf() {
+	let notifier = Notifier::default();
+	let config = ConnectionConfig {
+		send_buf: 0,
+		..ConnectionConfig::default()
+	};
+	let _ = Connection::connect_with(
+		(LOCALHOST, 0).into(),
+		(LOCALHOST, 1).into(),
+		&config,
+		&notifier,
+	);
+}
+
+#[test]
+fn shrink_grow_buffers_keeps_data() {
+	let notifier = Notifier::default();
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let config = ConnectionConfig::builder().idle_buf(16).build();
+	let mut connection = Connection::connect_with(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&config,
+		&notifier,
+	);
+	let (mut stream, _) = listener.accept().unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	let idle = match &mut connection {
+		Connection::Connected(connected) => {
+			assert!(connected.shrink_buffers());
+			connected.recv_space()
+		}
+		connection => panic!("{:?}", connection),
+	};
+	assert!(idle < config.recv_buf);
+	let data: Vec<u8> = (0..3 * idle as u32).map(|i| (i % 251) as u8).collect();
+	let writer = {
+		let data = data.clone();
+		thread::spawn(move || {
+			stream.write_all(&data).unwrap();
+			stream
+		})
+	};
+	drive(&mut connection, &notifier, |connection| {
+		connection.recv_avail() == Some(idle)
+	});
+	let mut received = Vec::new();
+	for _ in 0..idle / 2 {
+		received.push(connection.recv(&notifier).unwrap()());
+	}
+	// refill, wrapping around the shrunk ring
+	drive(&mut connection, &notifier, |connection| {
+		connection.recv_avail() == Some(idle)
+	});
+	let sent: Vec<u8> = (0..idle as u32 / 2).map(|i| (i % 13) as u8).collect();
+	for &byte in &sent {
+		connection.send(&notifier).unwrap()(byte);
+	}
+	match &mut connection {
+		Connection::Connected(connected) => {
+			// not idle, so left as is
+			assert!(!connected.shrink_buffers());
+			connected.grow_buffers(&notifier);
+		}
+		connection => panic!("{:?}", connection),
+	}
+	assert!(connection.recv_avail() == Some(idle));
+	while received.len() < data.len() {
+		drive(&mut connection, &notifier, |connection| {
+			connection.recv_avail() != Some(0)
+		});
+		while let Some(recv) = connection.recv(&notifier) {
+			received.push(recv());
+		}
+	}
+	assert!(received == data);
+	let mut stream = writer.join().unwrap();
+	drive(&mut connection, &notifier, |connection| match connection {
+		Connection::Connected(connected) => connected.send_buffered() == 0,
+		connection => panic!("{:?}", connection),
+	});
+	let mut flushed = vec![0; sent.len()];
+	stream.read_exact(&mut flushed).unwrap();
+	assert!(flushed == sent);
+	connection.kill(&notifier).unwrap()();
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn recv_timestamps() {
+	let notifier = Notifier::default();
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let mut connection = Connection::connect(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&notifier,
+	);
+	let (mut stream, _) = listener.accept().unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	match &mut connection {
+		Connection::Connected(connected) => connected.set_recv_timestamps(true),
+		connection => panic!("{:?}", connection),
+	}
+	// the kernel enables timestamping globally asynchronously, so the first segments may be missed
+	let start = time::Instant::now();
+	let timestamp = loop {
+		stream.write_all(&[1]).unwrap();
+		drive(&mut connection, &notifier, |connection| {
+			connection.recv_avail() != Some(0)
+		});
+		let _ = connection.recv(&notifier).unwrap()();
+		if let Connection::Connected(connected) = &connection {
+			if let Some(timestamp) = connected.last_recv_timestamp() {
+				break timestamp;
+			}
+		}
+		assert!(
+			start.elapsed() < time::Duration::from_secs(10),
+			"no timestamp"
+		);
+		thread::sleep(time::Duration::from_millis(10));
+	};
+	let now = time::SystemTime::now()
+		.duration_since(time::UNIX_EPOCH)
+		.unwrap();
+	assert!(
+		now - timestamp < time::Duration::from_secs(10),
+		"{:?} {:?}",
+		timestamp,
+		now
+	);
+	// still timestamped once closed locally
+	connection.close(&notifier).unwrap()();
+	stream.write_all(&[1]).unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		connection.recv_avail() != Some(0)
+	});
+	match &connection {
+		Connection::LocalClosed(local_closed) => {
+			assert!(local_closed.last_recv_timestamp().unwrap() >= timestamp);
+		}
+		connection => panic!("{:?}", connection),
+	}
+	connection.kill(&notifier).unwrap()();
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn accept_and_exchange() {
+	let notifier = Notifier::default();
+	let (mut listener, port) = Listener::new_ephemeral(&LOCALHOST, &notifier).unwrap();
+	let mut dialer =
+		Connection::connect((LOCALHOST, 0).into(), (LOCALHOST, port).into(), &notifier);
+	let mut accepted = accept(&mut listener, &notifier);
+	drive(&mut dialer, &notifier, |connection| {
+		!connection.connecting()
+	});
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.connecting()
+	});
+	exchange(&mut dialer, &mut accepted, &notifier);
+	exchange(&mut accepted, &mut dialer, &notifier);
+	dialer.close(&notifier).unwrap()();
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.recvable()
+	});
+	accepted.close(&notifier).unwrap()();
+	drive(&mut accepted, &notifier, Connection::is_terminal);
+	drive(&mut dialer, &notifier, Connection::is_terminal);
+	assert!(matches!(accepted, Connection::Closed), "{:?}", accepted);
+	assert!(matches!(dialer, Connection::Closed), "{:?}", dialer);
+	listener.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn connection_with_user_data() {
+	let notifier = Notifier::default();
+	let (mut listener, port) = Listener::new_ephemeral(&LOCALHOST, &notifier).unwrap();
+	let mut dialer = ConnectionWith::new(
+		Connection::connect((LOCALHOST, 0).into(), (LOCALHOST, port).into(), &notifier),
+		0_usize,
+	);
+	let mut accepted = ConnectionWith::new(accept(&mut listener, &notifier), 0_usize);
+	drive(&mut dialer, &notifier, |connection| {
+		!connection.connecting()
+	});
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.connecting()
+	});
+	exchange(&mut dialer, &mut accepted, &notifier);
+	// count what's received alongside the connection, as an application would
+	for byte in 0..=255 {
+		dialer.send(&notifier).unwrap()(byte);
+	}
+	dialer.poll(&notifier);
+	drive(&mut accepted, &notifier, |connection| {
+		connection.recv_avail() == Some(256)
+	});
+	let (connection, received) = accepted.split_mut();
+	while let Some(recv) = connection.recv(&notifier) {
+		assert_eq!(usize::from(recv()), *received);
+		*received += 1;
+	}
+	*dialer.user_data_mut() += 1;
+	dialer.close(&notifier).unwrap()();
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.recvable()
+	});
+	accepted.close(&notifier).unwrap()();
+	drive(&mut accepted, &notifier, Connection::is_terminal);
+	drive(&mut dialer, &notifier, Connection::is_terminal);
+	assert_eq!(accepted.into_inner().1, 256);
+	let (dialer, closes) = dialer.into_inner();
+	assert!(matches!(dialer, Connection::Closed), "{:?}", dialer);
+	assert_eq!(closes, 1);
+	listener.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn remote_closed_mid_handshake() {
+	let notifier = Notifier::default();
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let mut connection = Connection::connect(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&notifier,
+	);
+	let (mut stream, _) = listener.accept().unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	// a 4 byte preamble, of which the remote sends only half before closing
+	let mut preamble = Vec::new();
+	connection.handshake(&notifier).unwrap()(Box::new(move |stream: &mut HandshakeStream<'_>| {
+		let mut buf = [0; 4];
+		match stream.read(&mut buf[..4 - preamble.len()]) {
+			Ok(read) => preamble.extend_from_slice(&buf[..read]),
+			Err(err) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+		}
+		if preamble.len() == 4 {
+			HandshakeProgress::Done
+		} else {
+			HandshakeProgress::Pending
+		}
+	}));
+	assert!(
+		matches!(connection, Connection::Handshaking(_)),
+		"{:?}",
+		connection
+	);
+	stream.write_all(&[1, 2]).unwrap();
+	drop(stream);
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	assert!(
+		matches!(connection, Connection::RemoteClosed(_)),
+		"{:?}",
+		connection
+	);
+	connection.close(&notifier).unwrap()();
+	drive(&mut connection, &notifier, Connection::is_terminal);
+	assert!(matches!(connection, Connection::Closed), "{:?}", connection);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn shutdown_resets_at_deadline() {
+	let notifier = Notifier::default();
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let mut connection = Connection::connect(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&notifier,
+	);
+	let (stream, _) = listener.accept().unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	// the remote never closes, so this can't close gracefully
+	let mut shutdown = Shutdown::new(vec![connection], time::Duration::from_millis(50), &notifier);
+	let start = time::Instant::now();
+	let outcome = loop {
+		if let Some(outcome) = shutdown.poll(&notifier) {
+			break outcome;
+		}
+		assert!(start.elapsed() < time::Duration::from_secs(10), "stuck");
+		thread::sleep(time::Duration::from_millis(1));
+	};
+	assert_eq!(
+		outcome,
+		ShutdownOutcome {
+			closed: 0,
+			reset: 1,
+			errored: 0
+		}
+	);
+	assert!(notifier.fds.borrow().is_empty());
+	while stream.take_error().unwrap().is_none() {
+		assert!(start.elapsed() < time::Duration::from_secs(10), "not reset");
+		thread::sleep(time::Duration::from_millis(1));
+	}
+}
+
+#[test]
+fn self_connect() {
+	let notifier = Notifier::default();
+	let local = net::TcpListener::bind((LOCALHOST, 0))
+		.unwrap()
+		.local_addr()
+		.unwrap();
+	let mut connection = Connection::connect(local, local, &notifier);
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	assert!(
+		matches!(connection, Connection::Connected(_)),
+		"{:?}",
+		connection
+	);
+	connection.send(&notifier).unwrap()(1);
+	drive(&mut connection, &notifier, |connection| {
+		connection.recv_avail() != Some(0)
+	});
+	assert_eq!(connection.recv(&notifier).unwrap()(), 1);
+	connection.kill(&notifier).unwrap()();
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn simultaneous_open() {
+	let notifier = Notifier::default();
+	let a = net::TcpListener::bind((LOCALHOST, 0))
+		.unwrap()
+		.local_addr()
+		.unwrap();
+	let b = net::TcpListener::bind((LOCALHOST, 0))
+		.unwrap()
+		.local_addr()
+		.unwrap();
+	// Loopback refuses a SYN synchronously if nothing is at its destination yet. So first occupy b with a listener whose accept queue is full, which drops rather than refuses SYNs, leaving a's in flight until b's crosses it.
+	let full = {
+		use nix::sys::socket::{
+			self, sockopt, AddressFamily, InetAddr, SockAddr, SockFlag, SockType,
+		};
+		let fd = socket::socket(
+			AddressFamily::Inet,
+			SockType::Stream,
+			SockFlag::empty(),
+			None,
+		)
+		.unwrap();
+		socket::setsockopt(fd, sockopt::ReuseAddr, &true).unwrap();
+		socket::bind(fd, &SockAddr::new_inet(InetAddr::from_std(&b))).unwrap();
+		socket::listen(fd, 0).unwrap();
+		fd
+	};
+	let filler = net::TcpStream::connect(b).unwrap();
+	let mut dialer_a = Connection::connect(a, b, &notifier);
+	for _ in 0..10 {
+		thread::sleep(time::Duration::from_millis(1));
+		dialer_a.poll(&notifier);
+	}
+	assert!(dialer_a.connecting(), "{:?}", dialer_a);
+	nix::unistd::close(full).unwrap();
+	drop(filler);
+	let mut dialer_b = Connection::connect(b, a, &notifier);
+	drive(&mut dialer_b, &notifier, |connection| {
+		!connection.connecting()
+	});
+	drive(&mut dialer_a, &notifier, |connection| {
+		!connection.connecting()
+	});
+	assert!(
+		matches!(dialer_a, Connection::Connected(_)),
+		"{:?}",
+		dialer_a
+	);
+	assert!(
+		matches!(dialer_b, Connection::Connected(_)),
+		"{:?}",
+		dialer_b
+	);
+	match &dialer_a {
+		Connection::Connected(connected) => assert_eq!(connected.remote(), b),
+		connection => panic!("{:?}", connection),
+	}
+	dialer_a.send(&notifier).unwrap()(1);
+	dialer_a.poll(&notifier);
+	drive(&mut dialer_b, &notifier, |connection| {
+		connection.recv_avail() != Some(0)
+	});
+	assert_eq!(dialer_b.recv(&notifier).unwrap()(), 1);
+	dialer_a.kill(&notifier).unwrap()();
+	dialer_b.kill(&notifier).unwrap()();
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn probe_reset() {
+	let notifier = Notifier::default();
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let mut connection = Connection::connect(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&notifier,
+	);
+	let (stream, _) = listener.accept().unwrap();
+	drive(&mut connection, &notifier, |connection| {
+		!connection.connecting()
+	});
+	reset(stream);
+	thread::sleep(time::Duration::from_millis(50));
+	let probe = match &mut connection {
+		Connection::Connected(connected) => connected.probe(&notifier),
+		connection => panic!("{:?}", connection),
+	};
+	assert!(matches!(probe, ProbeResult::Dead(_)), "{:?}", probe);
+	// the error probe consumed is still acted on, rather than the poll seeing only EOF
+	connection.poll(&notifier);
+	assert!(matches!(connection, Connection::Killed), "{:?}", connection);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn forward_and_exchange() {
+	let notifier = Notifier::default();
+	let (forwarder, forwardee) = socket_forwarder();
+	let mut forwardee = Listener::with_socket_forwardee(forwardee, &notifier);
+	let (mut listener, port) = Listener::new_ephemeral(&LOCALHOST, &notifier).unwrap();
+	let mut dialer =
+		Connection::connect((LOCALHOST, 0).into(), (LOCALHOST, port).into(), &notifier);
+	// once connected, it's in the accept queue
+	drive(&mut dialer, &notifier, |connection| {
+		!connection.connecting()
+	});
+	let forwarded = listener.accept_all(&notifier, &mut |_, _| {
+		AcceptDecision::Forward(forwarder.clone())
+	});
+	assert!(forwarded.is_empty());
+	let mut accepted = accept(&mut forwardee, &notifier);
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.connecting()
+	});
+	exchange(&mut dialer, &mut accepted, &notifier);
+	exchange(&mut accepted, &mut dialer, &notifier);
+	dialer.kill(&notifier).unwrap()();
+	accepted.kill(&notifier).unwrap()();
+	listener.close(&notifier);
+	forwardee.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn forward_blocking() {
+	use std::os::unix::io::IntoRawFd;
+	let notifier = Notifier::default();
+	let (forwarder, forwardee) = socket_forwarder();
+	let mut forwardee = Listener::with_socket_forwardee(forwardee, &notifier);
+	let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let mut dialer = Connection::connect(
+		(LOCALHOST, 0).into(),
+		listener.local_addr().unwrap(),
+		&notifier,
+	);
+	// a blocking socket, as from a process that doesn't use this crate
+	let (stream, _) = listener.accept().unwrap();
+	forwarder.send(stream.into_raw_fd(), false).unwrap();
+	let mut accepted = accept(&mut forwardee, &notifier);
+	drive(&mut dialer, &notifier, |connection| {
+		!connection.connecting()
+	});
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.connecting()
+	});
+	exchange(&mut dialer, &mut accepted, &notifier);
+	exchange(&mut accepted, &mut dialer, &notifier);
+	dialer.kill(&notifier).unwrap()();
+	accepted.kill(&notifier).unwrap()();
+	forwardee.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[test]
+fn pump_relays_and_propagates_reset() {
+	let notifier = Notifier::default();
+	let front = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let back = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+	let a = Connection::connect(
+		(LOCALHOST, 0).into(),
+		front.local_addr().unwrap(),
+		&notifier,
+	);
+	let b = Connection::connect((LOCALHOST, 0).into(), back.local_addr().unwrap(), &notifier);
+	let (mut client, _) = front.accept().unwrap();
+	let (mut server, _) = back.accept().unwrap();
+	let mut pump = Pump::new(a, b, &notifier);
+	// more than fits in the buffers, so it's relayed in several goes
+	let data = (0..1_000_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+	let expected = data.clone();
+	let writer = thread::spawn(move || {
+		client.write_all(&data).unwrap();
+		client
+	});
+	let reader = thread::spawn(move || {
+		let mut received = vec![0; expected.len()];
+		server.read_exact(&mut received).unwrap();
+		assert!(received == expected);
+		server
+	});
+	let start = time::Instant::now();
+	while !reader.is_finished() {
+		assert!(
+			start.elapsed() < time::Duration::from_secs(10),
+			"{:?}",
+			pump
+		);
+		thread::sleep(time::Duration::from_millis(1));
+		let _ = pump.poll(&notifier);
+	}
+	reset(reader.join().unwrap());
+	while !pump.poll(&notifier) {
+		assert!(
+			start.elapsed() < time::Duration::from_secs(10),
+			"{:?}",
+			pump
+		);
+		thread::sleep(time::Duration::from_millis(1));
+	}
+	assert!(matches!(pump.a(), Connection::Killed), "{:?}", pump);
+	assert!(matches!(pump.b(), Connection::Killed), "{:?}", pump);
+	let mut client = writer.join().unwrap();
+	assert_eq!(
+		client.read(&mut [0]).unwrap_err().kind(),
+		io::ErrorKind::ConnectionReset
+	);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[cfg(feature = "framed")]
+#[test]
+fn framed_queue_limit() {
+	let notifier = Notifier::default();
+	let (mut listener, port) = Listener::new_ephemeral(&LOCALHOST, &notifier).unwrap();
+	let mut dialer =
+		Connection::connect((LOCALHOST, 0).into(), (LOCALHOST, port).into(), &notifier);
+	let mut accepted = accept(&mut listener, &notifier);
+	drive(&mut dialer, &notifier, |connection| {
+		!connection.connecting()
+	});
+	drive(&mut accepted, &notifier, |connection| {
+		!connection.connecting()
+	});
+	let framed = |connection| match connection {
+		Connection::Connected(connected) => {
+			tcp_typed::FrameConnection::new(connected, 1000, 4 * 1004)
+		}
+		connection => panic!("{:?}", connection),
+	};
+	let (mut dialer, mut accepted) = (framed(dialer), framed(accepted));
+	let frame = |i: usize| vec![i as u8; i % 1001];
+	// unpolled, the connection's send buffer fills, and then the queue
+	let mut sent = 0;
+	while dialer.send_frame(&frame(sent), &notifier) {
+		sent += 1;
+	}
+	assert!(!dialer.sendable());
+	assert!(dialer.send_queued() <= 4 * 1004);
+	let mut received = 0;
+	let start = time::Instant::now();
+	while received < sent {
+		assert!(start.elapsed() < time::Duration::from_secs(10), "stuck");
+		dialer.poll(&notifier);
+		accepted.poll(&notifier);
+		while let Some(got) = accepted.poll_frame(&notifier) {
+			assert!(got == frame(received));
+			received += 1;
+		}
+	}
+	assert!(dialer.sendable());
+	dialer.kill(&notifier);
+	accepted.kill(&notifier);
+	listener.close(&notifier);
+	assert!(notifier.fds.borrow().is_empty());
+}
+
+#[cfg(feature = "async")]
+mod async_connect {
+	use super::*;
+	use std::{
+		collections::HashMap,
+		future::Future,
+		pin::pin,
+		sync::{
+			atomic::{AtomicBool, Ordering},
+			Arc,
+		},
+		task::{Context, Poll, Wake, Waker},
+	};
+	use tcp_typed::WakerNotifier;
+
+	// Wakes on each tick for every registered fd, which is a superset of the edge-triggered events it should wake for.
+	#[derive(Default)]
+	struct Reactor {
+		fds: RefCell<HashMap<RawFd, Waker>>,
+		instants: RefCell<Vec<(time::Instant, Waker)>>,
+	}
+	impl tcp_typed::Reactor for Reactor {
+		type InstantSlot = ();
+		fn add_fd(&self, fd: RawFd, waker: &Waker) {
+			assert!(
+				self.fds.borrow_mut().insert(fd, waker.clone()).is_none(),
+				"fd {} added twice",
+				fd
+			);
+		}
+		fn remove_fd(&self, fd: RawFd) {
+			assert!(
+				self.fds.borrow_mut().remove(&fd).is_some(),
+				"fd {} removed but never added",
+				fd
+			);
+		}
+		fn add_instant(&self, instant: time::Instant, waker: &Waker) -> Self::InstantSlot {
+			self.instants.borrow_mut().push((instant, waker.clone()));
+		}
+		fn remove_instant(&self, _slot: Self::InstantSlot) {}
+	}
+	impl Reactor {
+		fn tick(&self) {
+			thread::sleep(time::Duration::from_millis(1));
+			for waker in self.fds.borrow().values() {
+				waker.wake_by_ref();
+			}
+			let now = time::Instant::now();
+			self.instants.borrow_mut().retain(|(instant, waker)| {
+				if *instant <= now {
+					waker.wake_by_ref();
+				}
+				*instant > now
+			});
+		}
+	}
+
+	#[derive(Default)]
+	struct Woken(AtomicBool);
+	impl Wake for Woken {
+		fn wake(self: Arc<Self>) {
+			self.0.store(true, Ordering::Relaxed);
+		}
+	}
+
+	// Poll `future` each time it's woken, ticking the reactor in between.
+	fn block_on<F: Future>(future: F, reactor: &Reactor) -> F::Output {
+		let woken = Arc::new(Woken(AtomicBool::new(true)));
+		let waker = Waker::from(woken.clone());
+		let mut future = pin!(future);
+		let start = time::Instant::now();
+		loop {
+			if woken.0.swap(false, Ordering::Relaxed) {
+				if let Poll::Ready(ret) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+					return ret;
+				}
+			}
+			assert!(start.elapsed() < time::Duration::from_secs(10), "stuck");
+			reactor.tick();
+		}
+	}
+
+	#[test]
+	fn connects() {
+		let reactor = Reactor::default();
+		let notifier = WakerNotifier::new(&reactor);
+		let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+		let connected = block_on(
+			tcp_typed::connect(
+				(LOCALHOST, 0).into(),
+				listener.local_addr().unwrap(),
+				time::Duration::from_secs(10),
+				&notifier,
+			),
+			&reactor,
+		)
+		.unwrap();
+		let _ = listener.accept().unwrap();
+		connected.kill(&notifier);
+		assert!(reactor.fds.borrow().is_empty());
+	}
+
+	#[test]
+	fn times_out() {
+		let reactor = Reactor::default();
+		let notifier = WakerNotifier::new(&reactor);
+		let (_refused, remote) = refused_addr();
+		let err = block_on(
+			tcp_typed::connect(
+				(LOCALHOST, 0).into(),
+				remote,
+				time::Duration::from_millis(100),
+				&notifier,
+			),
+			&reactor,
+		)
+		.unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+		assert!(reactor.fds.borrow().is_empty());
+	}
+}